@@ -0,0 +1,330 @@
+//! Detection of the bodies that transformations apply to.
+//!
+//! By default, a response body is transformed as JSON if its content type is
+//! `application/json` or `application/*+json`. The `content_types` setting
+//! replaces that list with globs (e.g. `text/*`), `json_without_content_type`
+//! covers responses without a content type, and `sniff_json` transforms
+//! responses without a content type or with `text/plain` if their body starts
+//! like a JSON object or array. Sniffing happens after the response headers
+//! are forwarded, so those responses lose their `content-length` even if they
+//! turn out not to be JSON.
+//!
+//! XML transformations apply to `application/xml`, `text/xml` and `*/*+xml`
+//! bodies, and form transformations to `application/x-www-form-urlencoded`
+//! bodies. JSON transformations apply to each line of `application/x-ndjson`
+//! and `application/jsonl` bodies, and to the data of each event of
+//! `text/event-stream` bodies. `regex_replace` rules apply to text bodies:
+//! `text/*`, JSON, NDJSON, XML, form and JavaScript bodies, and any body that
+//! is transformed as JSON.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use regex::Regex;
+
+use crate::matcher::glob;
+
+pub(crate) fn is_json_mime_type<T: AsRef<str>>(ct: T) -> bool {
+    let Ok(mt) = ct.as_ref().parse::<mime::Mime>() else {
+        return false;
+    };
+
+    matches!(
+        (mt.type_(), mt.subtype(), mt.suffix()),
+        (mime::APPLICATION, mime::JSON, _) | (mime::APPLICATION, _, Some(mime::JSON))
+    )
+}
+
+pub(crate) fn is_ndjson_mime_type<T: AsRef<str>>(ct: T) -> bool {
+    let Ok(mt) = ct.as_ref().parse::<mime::Mime>() else {
+        return false;
+    };
+
+    mt.type_() == mime::APPLICATION && (mt.subtype() == "x-ndjson" || mt.subtype() == "jsonl")
+}
+
+pub(crate) fn is_event_stream_mime_type<T: AsRef<str>>(ct: T) -> bool {
+    let Ok(mt) = ct.as_ref().parse::<mime::Mime>() else {
+        return false;
+    };
+
+    mt.type_() == mime::TEXT && mt.subtype() == mime::EVENT_STREAM
+}
+
+pub(crate) fn is_xml_mime_type<T: AsRef<str>>(ct: T) -> bool {
+    let Ok(mt) = ct.as_ref().parse::<mime::Mime>() else {
+        return false;
+    };
+
+    matches!(
+        (mt.type_(), mt.subtype(), mt.suffix()),
+        (mime::APPLICATION | mime::TEXT, mime::XML, _) | (_, _, Some(mime::XML))
+    )
+}
+
+pub(crate) fn is_form_mime_type<T: AsRef<str>>(ct: T) -> bool {
+    let Ok(mt) = ct.as_ref().parse::<mime::Mime>() else {
+        return false;
+    };
+
+    mt.type_() == mime::APPLICATION && mt.subtype() == mime::WWW_FORM_URLENCODED
+}
+
+pub(crate) fn is_text_mime_type<T: AsRef<str>>(ct: T) -> bool {
+    let ct = ct.as_ref();
+    let Ok(mt) = ct.parse::<mime::Mime>() else {
+        return false;
+    };
+
+    mt.type_() == mime::TEXT
+        || (mt.type_() == mime::APPLICATION && mt.subtype() == mime::JAVASCRIPT)
+        || is_json_mime_type(ct)
+        || is_ndjson_mime_type(ct)
+        || is_xml_mime_type(ct)
+        || is_form_mime_type(ct)
+}
+
+/// Returns whether a body starts like a JSON object or array, or `None` if
+/// there is only whitespace so far.
+pub(crate) fn sniff_json(body: &[u8]) -> Option<bool> {
+    body.iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .map(|b| matches!(b, b'{' | b'['))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidContentType {
+    pattern: String,
+    reason: String,
+}
+
+impl fmt::Display for InvalidContentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid content type pattern {:?}: {}",
+            self.pattern, self.reason
+        )
+    }
+}
+
+/// The content types of JSON bodies.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ContentTypes {
+    /// Globs matched against the type and subtype. If empty, the default
+    /// JSON types are used.
+    patterns: Vec<Regex>,
+    /// Whether a body without a content type is JSON.
+    pub(crate) missing: bool,
+    /// Whether a response body without a content type, or with `text/plain`,
+    /// is transformed if it looks like JSON.
+    pub(crate) sniff: bool,
+}
+
+impl TryFrom<&[String]> for ContentTypes {
+    type Error = InvalidContentType;
+
+    fn try_from(patterns: &[String]) -> Result<Self, Self::Error> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let err = |reason: String| InvalidContentType {
+                    pattern: pattern.clone(),
+                    reason,
+                };
+
+                if !pattern.contains('/') {
+                    return Err(err("expected <type>/<subtype>".to_owned()));
+                }
+
+                glob(pattern, '/', true).map_err(|e| err(e.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(ContentTypes {
+            patterns,
+            ..Default::default()
+        })
+    }
+}
+
+impl ContentTypes {
+    /// Returns whether a content type is that of a JSON body.
+    pub(crate) fn is_json(&self, content_type: Option<&str>) -> bool {
+        let Some(ct) = content_type else {
+            return self.missing;
+        };
+
+        if self.patterns.is_empty() {
+            return is_json_mime_type(ct);
+        }
+
+        ct.parse::<mime::Mime>()
+            .is_ok_and(|mt| self.patterns.iter().any(|p| p.is_match(mt.essence_str())))
+    }
+
+    /// Returns whether a body with a content type that is not that of JSON
+    /// bodies is sniffed. Other content types are left alone, so that their
+    /// responses keep their `content-length`.
+    pub(crate) fn sniffs(&self, content_type: Option<&str>) -> bool {
+        let Some(ct) = content_type else {
+            return self.sniff;
+        };
+
+        self.sniff
+            && ct
+                .parse::<mime::Mime>()
+                .is_ok_and(|mt| mt.type_() == mime::TEXT && mt.subtype() == mime::PLAIN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_types(patterns: &[&str]) -> ContentTypes {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        ContentTypes::try_from(patterns.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_json_mime_type_detection() {
+        assert!(is_json_mime_type("application/json"));
+        assert!(is_json_mime_type("APPLICATION/json"));
+        assert!(is_json_mime_type("APPLICATION/JSON"));
+        assert!(is_json_mime_type("application/JSON"));
+        assert!(is_json_mime_type("application/json; charset=utf-8"));
+        assert!(is_json_mime_type("application/problem+json"));
+        assert!(is_json_mime_type("application/problem+JSON"));
+        assert!(is_json_mime_type("application/problem+json; charset=utf-8"));
+
+        assert!(!is_json_mime_type("text/plain"));
+        assert!(!is_json_mime_type("application/not-json"));
+        assert!(!is_json_mime_type("nope/json"));
+    }
+
+    #[test]
+    fn test_ndjson_mime_type_detection() {
+        assert!(is_ndjson_mime_type("application/x-ndjson"));
+        assert!(is_ndjson_mime_type("Application/JSONL; charset=utf-8"));
+
+        assert!(!is_ndjson_mime_type("application/json"));
+        assert!(!is_ndjson_mime_type("text/x-ndjson"));
+    }
+
+    #[test]
+    fn test_event_stream_mime_type_detection() {
+        assert!(is_event_stream_mime_type("text/event-stream"));
+        assert!(is_event_stream_mime_type(
+            "Text/Event-Stream; charset=utf-8"
+        ));
+
+        assert!(!is_event_stream_mime_type("application/event-stream"));
+        assert!(!is_event_stream_mime_type("text/plain"));
+    }
+
+    #[test]
+    fn test_xml_mime_type_detection() {
+        assert!(is_xml_mime_type("application/xml"));
+        assert!(is_xml_mime_type("Text/XML; charset=utf-8"));
+        assert!(is_xml_mime_type("application/soap+xml"));
+        assert!(is_xml_mime_type("image/svg+xml"));
+
+        assert!(!is_xml_mime_type("application/json"));
+        assert!(!is_xml_mime_type("application/xml-dtd"));
+        assert!(!is_xml_mime_type("xml"));
+    }
+
+    #[test]
+    fn test_form_mime_type_detection() {
+        assert!(is_form_mime_type("application/x-www-form-urlencoded"));
+        assert!(is_form_mime_type(
+            "Application/X-WWW-Form-Urlencoded; charset=utf-8"
+        ));
+
+        assert!(!is_form_mime_type("multipart/form-data"));
+        assert!(!is_form_mime_type("application/json"));
+    }
+
+    #[test]
+    fn test_text_mime_type_detection() {
+        assert!(is_text_mime_type("text/html; charset=utf-8"));
+        assert!(is_text_mime_type("Text/Event-Stream"));
+        assert!(is_text_mime_type("application/javascript"));
+        assert!(is_text_mime_type("application/problem+json"));
+        assert!(is_text_mime_type("application/x-ndjson"));
+        assert!(is_text_mime_type("image/svg+xml"));
+        assert!(is_text_mime_type("application/x-www-form-urlencoded"));
+
+        assert!(!is_text_mime_type("application/octet-stream"));
+        assert!(!is_text_mime_type("image/png"));
+        assert!(!is_text_mime_type("application/pdf"));
+        assert!(!is_text_mime_type("text"));
+    }
+
+    #[test]
+    fn test_content_types_default() {
+        let types = ContentTypes::default();
+
+        assert!(types.is_json(Some("application/problem+json")));
+        assert!(!types.is_json(Some("text/plain")));
+        assert!(!types.is_json(None));
+
+        let types = ContentTypes {
+            missing: true,
+            ..Default::default()
+        };
+        assert!(types.is_json(None));
+        assert!(!types.is_json(Some("text/plain")));
+    }
+
+    #[test]
+    fn test_content_types_patterns() {
+        let types = content_types(&["application/json", "text/*"]);
+
+        assert!(types.is_json(Some("application/json")));
+        assert!(types.is_json(Some("Text/Plain; charset=utf-8")));
+        assert!(types.is_json(Some("text/javascript")));
+        assert!(!types.is_json(Some("application/problem+json")));
+        assert!(!types.is_json(Some("not a content type")));
+        assert!(!types.is_json(None));
+    }
+
+    #[test]
+    fn test_content_types_sniff() {
+        let types = ContentTypes {
+            sniff: true,
+            ..Default::default()
+        };
+
+        assert!(types.sniffs(None));
+        assert!(types.sniffs(Some("Text/Plain; charset=utf-8")));
+        assert!(!types.sniffs(Some("text/html")));
+        assert!(!types.sniffs(Some("application/octet-stream")));
+        assert!(!types.sniffs(Some("not a content type")));
+
+        assert!(!ContentTypes::default().sniffs(None));
+        assert!(!ContentTypes::default().sniffs(Some("text/plain")));
+    }
+
+    #[test]
+    fn test_content_types_invalid() {
+        let patterns = vec!["json".to_string()];
+        let err = ContentTypes::try_from(patterns.as_slice()).unwrap_err();
+
+        assert_eq!(
+            r#"Invalid content type pattern "json": expected <type>/<subtype>"#,
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_sniff_json() {
+        assert_eq!(Some(true), sniff_json(b" \n{\"a\": 1}"));
+        assert_eq!(Some(true), sniff_json(b"[1]"));
+        assert_eq!(Some(false), sniff_json(b"<html>"));
+        assert_eq!(Some(false), sniff_json(b"\"string\""));
+        assert_eq!(None, sniff_json(b"  \r\n"));
+        assert_eq!(None, sniff_json(b""));
+    }
+}
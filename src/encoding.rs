@@ -0,0 +1,240 @@
+//! Compressed response bodies.
+//!
+//! Bodies with a supported `content-encoding` are decoded before they are
+//! transformed and, unless `on_compressed` is `decompress`, re-encoded
+//! afterwards.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read};
+
+use flate2::read::{DeflateDecoder, GzEncoder, MultiGzDecoder, ZlibDecoder, ZlibEncoder};
+use flate2::Compression;
+use serde::Deserialize;
+
+/// Brotli quality (0-11) and window size used for re-encoding. These favour
+/// speed over size, as the body is compressed on the request path.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const BUFFER_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UnsupportedEncoding(String);
+
+impl fmt::Display for UnsupportedEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unsupported content-encoding => {:?}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ContentEncoding {
+    Gzip,
+    Deflate,
+    Brotli,
+}
+
+impl fmt::Display for ContentEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+            ContentEncoding::Brotli => "br",
+        })
+    }
+}
+
+impl TryFrom<&str> for ContentEncoding {
+    type Error = UnsupportedEncoding;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.trim().to_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(ContentEncoding::Gzip),
+            "deflate" => Ok(ContentEncoding::Deflate),
+            "br" => Ok(ContentEncoding::Brotli),
+            _ => Err(UnsupportedEncoding(value.to_owned())),
+        }
+    }
+}
+
+impl ContentEncoding {
+    /// Parses a `content-encoding` header. Returns `None` for uncompressed
+    /// bodies. Multiple (stacked) encodings are not supported.
+    pub(crate) fn parse(header: Option<&str>) -> Result<Option<Self>, UnsupportedEncoding> {
+        match header.map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(value) if value.eq_ignore_ascii_case("identity") => Ok(None),
+            Some(value) => ContentEncoding::try_from(value).map(Some),
+        }
+    }
+
+    /// Decodes a body. If `limit` is given, decoding stops once the decoded
+    /// body is larger than `limit`, so callers can check for that without
+    /// decoding all of it.
+    pub(crate) fn decode(&self, body: &[u8], limit: Option<usize>) -> io::Result<Vec<u8>> {
+        let limit = limit.map_or(u64::MAX, |limit| limit as u64 + 1);
+        let mut decoded = vec![];
+
+        match self {
+            // a gzip body may consist of several members, which are concatenated
+            ContentEncoding::Gzip => {
+                MultiGzDecoder::new(body)
+                    .take(limit)
+                    .read_to_end(&mut decoded)?;
+            }
+            // `deflate` is meant to be zlib-wrapped, but some servers send a
+            // raw deflate stream instead
+            ContentEncoding::Deflate => {
+                if ZlibDecoder::new(body)
+                    .take(limit)
+                    .read_to_end(&mut decoded)
+                    .is_err()
+                {
+                    decoded.clear();
+                    DeflateDecoder::new(body)
+                        .take(limit)
+                        .read_to_end(&mut decoded)?;
+                }
+            }
+            ContentEncoding::Brotli => {
+                brotli::Decompressor::new(body, BUFFER_SIZE)
+                    .take(limit)
+                    .read_to_end(&mut decoded)?;
+            }
+        }
+
+        Ok(decoded)
+    }
+
+    pub(crate) fn encode(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encoded = vec![];
+
+        match self {
+            ContentEncoding::Gzip => {
+                GzEncoder::new(body, Compression::default()).read_to_end(&mut encoded)?;
+            }
+            ContentEncoding::Deflate => {
+                ZlibEncoder::new(body, Compression::default()).read_to_end(&mut encoded)?;
+            }
+            ContentEncoding::Brotli => {
+                brotli::CompressorReader::new(body, BUFFER_SIZE, BROTLI_QUALITY, BROTLI_WINDOW)
+                    .read_to_end(&mut encoded)?;
+            }
+        }
+
+        Ok(encoded)
+    }
+}
+
+/// What happens to the encoding of a compressed body that is transformed.
+#[derive(Deserialize, Default, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CompressedPolicy {
+    /// Re-encode the body with its original encoding.
+    #[default]
+    Recompress,
+    /// Forward the body decoded and remove the `content-encoding` header.
+    /// Since the header is removed before the body arrives, a body that is
+    /// larger than `max_body_size` once decoded, or fails to decode, can't be
+    /// forwarded untransformed and is rejected instead.
+    Decompress,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] =
+        br#"{"id": 1, "secret": "x", "padding": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"}"#;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Ok(None), ContentEncoding::parse(None));
+        assert_eq!(Ok(None), ContentEncoding::parse(Some("")));
+        assert_eq!(Ok(None), ContentEncoding::parse(Some("Identity")));
+        assert_eq!(
+            Ok(Some(ContentEncoding::Gzip)),
+            ContentEncoding::parse(Some("GZIP"))
+        );
+        assert_eq!(
+            Ok(Some(ContentEncoding::Gzip)),
+            ContentEncoding::parse(Some("x-gzip"))
+        );
+        assert_eq!(
+            Ok(Some(ContentEncoding::Deflate)),
+            ContentEncoding::parse(Some(" deflate "))
+        );
+        assert_eq!(
+            Ok(Some(ContentEncoding::Brotli)),
+            ContentEncoding::parse(Some("br"))
+        );
+
+        for value in ["compress", "zstd", "gzip, br"] {
+            assert!(ContentEncoding::parse(Some(value)).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for encoding in [
+            ContentEncoding::Gzip,
+            ContentEncoding::Deflate,
+            ContentEncoding::Brotli,
+        ] {
+            let encoded = encoding.encode(BODY).unwrap();
+            assert_ne!(BODY, encoded.as_slice(), "{}", encoding);
+            assert_eq!(
+                BODY,
+                encoding.decode(&encoded, None).unwrap(),
+                "{}",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_raw_deflate() {
+        let mut encoded = vec![];
+        flate2::read::DeflateEncoder::new(BODY, Compression::default())
+            .read_to_end(&mut encoded)
+            .unwrap();
+
+        assert_eq!(
+            BODY,
+            ContentEncoding::Deflate.decode(&encoded, None).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decode_gzip_members() {
+        let (first, second) = BODY.split_at(20);
+        let mut encoded = ContentEncoding::Gzip.encode(first).unwrap();
+        encoded.extend(ContentEncoding::Gzip.encode(second).unwrap());
+
+        assert_eq!(BODY, ContentEncoding::Gzip.decode(&encoded, None).unwrap());
+    }
+
+    #[test]
+    fn test_decode_limit() {
+        let encoded = ContentEncoding::Gzip.encode(BODY).unwrap();
+
+        let decoded = ContentEncoding::Gzip.decode(&encoded, Some(10)).unwrap();
+        assert_eq!(11, decoded.len());
+
+        let decoded = ContentEncoding::Gzip
+            .decode(&encoded, Some(BODY.len()))
+            .unwrap();
+        assert_eq!(BODY, decoded);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        for encoding in [
+            ContentEncoding::Gzip,
+            ContentEncoding::Deflate,
+            ContentEncoding::Brotli,
+        ] {
+            assert!(encoding.decode(BODY, None).is_err(), "{}", encoding);
+        }
+    }
+}
@@ -1,8 +1,35 @@
+mod content_type;
+mod encoding;
+mod form;
 mod json;
+mod matcher;
+mod merge;
+mod ndjson;
+mod patch;
+mod path;
+mod request;
+mod scan;
+mod splice;
+mod sse;
+mod status;
+mod stream;
+mod template;
+mod text;
 mod types;
+mod xml;
 
+use std::fmt;
+use std::io;
 use std::rc::Rc;
 
+use crate::content_type::*;
+use crate::encoding::{CompressedPolicy, ContentEncoding};
+use crate::ndjson::NdjsonStream;
+use crate::request::*;
+use crate::sse::EventStream;
+use crate::stream::{JsonStream, StreamPlan};
+use crate::template::{Template, Variable};
+use crate::text::RegexReplace;
 use crate::types::*;
 use log::*;
 
@@ -17,18 +44,144 @@ proxy_wasm::main! {{
    });
 }}
 
+const CONTENT_ENCODING: &str = "content-encoding";
 const CONTENT_LENGTH: &str = "content-length";
 const CONTENT_TYPE: &str = "content-type";
 
-fn is_json_mime_type<T: AsRef<str>>(ct: T) -> bool {
-    let Ok(mt) = ct.as_ref().parse::<mime::Mime>() else {
-        return false;
+#[derive(Debug)]
+enum DecodeError {
+    /// The decoded body is larger than `max_body_size`.
+    Oversize(usize),
+    Invalid(io::Error),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Oversize(max) => write!(f, "decoded body exceeds max_body_size ({})", max),
+            DecodeError::Invalid(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Decodes a compressed body, without inflating more than `max_body_size`
+/// bytes of it.
+fn decode_body(
+    encoding: ContentEncoding,
+    body: &[u8],
+    max_body_size: Option<usize>,
+) -> Result<Vec<u8>, DecodeError> {
+    let decoded = encoding
+        .decode(body, max_body_size)
+        .map_err(DecodeError::Invalid)?;
+
+    match max_body_size {
+        Some(max) if decoded.len() > max => Err(DecodeError::Oversize(max)),
+        _ => Ok(decoded),
+    }
+}
+
+/// Applies transformations to a JSON body, returning the new body if it was
+/// changed.
+fn transform_json<F>(body: &[u8], transform: F) -> Option<Vec<u8>>
+where
+    F: FnOnce(&mut JsonValue) -> bool,
+{
+    let mut json = match serde_json::from_slice(body) {
+        Ok(value @ (JsonValue::Object(_) | JsonValue::Array(_))) => value,
+        Ok(other) => {
+            warn!(
+                "invalid body type (expected: object or array, got: {}), exiting",
+                json::type_name(other)
+            );
+            return None;
+        }
+        Err(e) => {
+            warn!("body was invalid JSON ({}), exiting", e);
+            return None;
+        }
+    };
+
+    if !transform(&mut json) {
+        info!("no body changes were applied");
+        return None;
+    }
+
+    match splice::splice(body, &json) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            error!("failed to re-serialize JSON body ({}), exiting", e);
+            None
+        }
+    }
+}
+
+/// Applies transformations to a form body, returning the new body if it was
+/// changed.
+fn transform_form<F>(body: &[u8], transform: F) -> Option<Vec<u8>>
+where
+    F: FnOnce(&mut Vec<(String, String)>) -> bool,
+{
+    let Ok(body) = std::str::from_utf8(body) else {
+        warn!("form body was not valid UTF-8, exiting");
+        return None;
     };
 
-    matches!(
-        (mt.type_(), mt.subtype(), mt.suffix()),
-        (mime::APPLICATION, mime::JSON, _) | (mime::APPLICATION, _, Some(mime::JSON))
-    )
+    let mut fields = form::parse(body);
+
+    if !transform(&mut fields) {
+        info!("no body changes were applied");
+        return None;
+    }
+
+    Some(form::reserialize(body, &fields).into_bytes())
+}
+
+/// Applies transformations to the query string arguments of a request path,
+/// returning the new path if it was changed. Arguments that are left as they
+/// are keep their original encoding.
+fn transform_query<F>(path: &str, transform: F) -> Option<String>
+where
+    F: FnOnce(&mut Vec<(String, String)>) -> bool,
+{
+    let (base, query) = path.split_once('?').unwrap_or((path, ""));
+    let mut args = form::parse(query);
+
+    if !transform(&mut args) {
+        return None;
+    }
+
+    match form::reserialize(query, &args) {
+        query if query.is_empty() => Some(base.to_owned()),
+        query => Some(format!("{}?{}", base, query)),
+    }
+}
+
+/// Applies transformations to an XML body, returning the new body if it was
+/// changed.
+fn transform_xml<F>(body: &[u8], transform: F) -> Option<Vec<u8>>
+where
+    F: FnOnce(&mut xml::Document) -> bool,
+{
+    let Ok(body) = std::str::from_utf8(body) else {
+        warn!("XML body was not valid UTF-8, exiting");
+        return None;
+    };
+
+    let mut doc = match xml::Document::parse(body) {
+        Ok(doc) => doc,
+        Err(e) => {
+            warn!("body was invalid XML ({}), exiting", e);
+            return None;
+        }
+    };
+
+    if !transform(&mut doc) {
+        info!("no body changes were applied");
+        return None;
+    }
+
+    Some(doc.to_string().into_bytes())
 }
 
 struct ResponseTransformerRoot {
@@ -49,16 +202,24 @@ impl RootContext for ResponseTransformerRoot {
             return false;
         };
 
-        match serde_json::from_slice::<ConfigInput>(bytes.as_slice()) {
-            Ok(user_config) => {
-                self.config = Some(Rc::new(user_config.into()));
+        let user_config = match serde_json::from_slice::<ConfigInput>(bytes.as_slice()) {
+            Ok(user_config) => user_config,
+            Err(e) => {
+                error!("failed to parse configuration: {:?}", e);
+                return false;
+            }
+        };
+
+        match Config::try_from(user_config) {
+            Ok(config) => {
+                self.config = Some(Rc::new(config));
 
                 info!("new configuration: {:#?}", &self.config);
 
                 true
             }
             Err(e) => {
-                error!("failed to parse configuration: {:?}", e);
+                error!("invalid configuration: {}", e);
                 false
             }
         }
@@ -74,7 +235,20 @@ impl RootContext for ResponseTransformerRoot {
 
         let config = config.clone();
 
-        Some(Box::new(ResponseTransformerHttp { config, id }))
+        Some(Box::new(ResponseTransformerHttp {
+            config,
+            id,
+            request: RequestInfo::default(),
+            request_rules: vec![],
+            status: None,
+            rules: vec![],
+            format: None,
+            text: false,
+            body: BodyMode::Undecided,
+            request_body: BodyMode::Undecided,
+            encoding: None,
+            decompressing: false,
+        }))
     }
 
     fn get_type(&self) -> Option<ContextType> {
@@ -85,29 +259,337 @@ impl RootContext for ResponseTransformerRoot {
 struct ResponseTransformerHttp {
     config: Rc<Config>,
     id: u32,
+    request: RequestInfo,
+    /// Indices of the rules whose request transformations match the request.
+    request_rules: Vec<usize>,
+    status: Option<u16>,
+    /// Indices of the rules that match the request and response.
+    rules: Vec<usize>,
+    /// The format of the response body, if it is transformed.
+    format: Option<BodyFormat>,
+    /// Whether the response body is text, which `regex_replace` rules apply
+    /// to.
+    text: bool,
+    body: BodyMode,
+    /// Only `Undecided` while the request body is buffered, or the mode of a
+    /// request body that is larger than `max_body_size`.
+    request_body: BodyMode,
+    /// The encoding of a compressed response body that is transformed.
+    encoding: Option<ContentEncoding>,
+    /// Whether the `content-encoding` header was removed, so the body can no
+    /// longer be forwarded as it is.
+    decompressing: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFormat {
+    Json,
+    /// Newline-delimited JSON, transformed line by line.
+    Ndjson,
+    /// Server-sent events, of which the data is transformed event by event.
+    EventStream,
+    Xml,
+    Form,
+    /// Any other body, which only regular expressions are applied to.
+    Text,
+}
+
+/// A body made of separate JSON documents: NDJSON lines or server-sent
+/// events.
+enum DocumentStream {
+    Ndjson(NdjsonStream),
+    Events(EventStream),
+}
+
+impl DocumentStream {
+    fn feed(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        match self {
+            DocumentStream::Ndjson(lines) => lines.feed(chunk, out),
+            DocumentStream::Events(events) => events.feed(chunk, out),
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        match self {
+            DocumentStream::Ndjson(lines) => lines.finish(out),
+            DocumentStream::Events(events) => events.finish(out),
+        }
+    }
+}
+
+/// How the response body is transformed, decided on its first chunk.
+enum BodyMode {
+    Undecided,
+    /// Chunks are transformed and forwarded as they arrive.
+    Stream(Box<JsonStream>),
+    /// Complete documents are transformed and forwarded as they arrive.
+    Documents(Box<DocumentStream>),
+    /// The body is buffered and transformed once it is complete.
+    Buffer,
+    /// The body is forwarded as-is, because it is larger than
+    /// `max_body_size`, has an unsupported encoding or does not look like
+    /// JSON.
+    Passthrough,
+    /// The body is larger than `max_body_size` and dropped.
+    Truncate,
+    /// The body is larger than `max_body_size` and an error response (413
+    /// for a request, 502 for a response) has been sent.
+    Rejected,
 }
 
 impl Context for ResponseTransformerHttp {}
 
 impl HttpContext for ResponseTransformerHttp {
-    fn on_http_response_headers(&mut self, num_headers: usize, end_of_stream: bool) -> Action {
+    fn on_http_request_headers(&mut self, num_headers: usize, end_of_stream: bool) -> Action {
         info!(
-            "{} on_http_response_headers, num_headers: {}, eof: {}",
+            "{} on_http_request_headers, num_headers: {}, eof: {}",
             self.id, num_headers, end_of_stream
         );
 
-        if self.config.json.is_some() && self.is_json_response() {
-            info!(
-                "removing {} header for body transformations",
-                CONTENT_LENGTH
+        self.request.method = self.get_http_request_header(":method");
+        self.request.authority = self.get_http_request_header(":authority");
+
+        if let Some(path) = self.get_http_request_header(":path") {
+            self.request.set_path(&path);
+        }
+
+        for name in &self.config.request_headers {
+            if let Some(value) = self.get_http_request_header(name) {
+                self.request.headers.insert(name.clone(), value);
+            }
+        }
+
+        self.request_rules = (0..self.config.rules.len())
+            .filter(|i| self.config.rules[*i].matches_request(&self.request))
+            .collect();
+
+        if self.request_rules.is_empty() {
+            return Action::Continue;
+        }
+
+        let mut headers = self.get_http_request_headers();
+        let mut changed = false;
+
+        for tx in self.active_request_transformations() {
+            if let Some(header_tx) = &tx.headers {
+                changed |= header_tx.transform(FieldKind::Header, &mut headers, |t| self.render(t));
+            }
+        }
+
+        if self
+            .active_request_transformations()
+            .any(|tx| tx.querystring.is_some())
+        {
+            changed |= self.transform_querystring(&mut headers);
+        }
+
+        let content_type = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(CONTENT_TYPE))
+            .map(|(_, value)| value.as_str());
+
+        if !end_of_stream && self.is_request_body_transformed(content_type) {
+            let is_content_length = |name: &str| name.eq_ignore_ascii_case(CONTENT_LENGTH);
+            let content_length = headers
+                .iter()
+                .find(|(name, _)| is_content_length(name))
+                .and_then(|(_, value)| value.parse().ok());
+
+            if content_length.is_some_and(|size| self.check_request_body_size(size)) {
+                match self.request_body {
+                    BodyMode::Rejected => return Action::Pause,
+                    BodyMode::Truncate => {
+                        for (_, value) in headers
+                            .iter_mut()
+                            .filter(|(name, _)| is_content_length(name))
+                        {
+                            *value = "0".to_owned();
+                        }
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            } else {
+                info!(
+                    "removing {} header for request body transformations",
+                    CONTENT_LENGTH
+                );
+                headers.retain(|(name, _)| !is_content_length(name));
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.set_http_request_headers(
+                headers
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str()))
+                    .collect(),
             );
-            self.set_http_response_header(CONTENT_LENGTH, None);
         }
 
-        if let Some(header_tx) = &self.config.headers {
-            self.transform_headers(header_tx);
+        Action::Continue
+    }
+
+    fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        info!(
+            "{} on_http_request_body, body_size: {}, eof: {}",
+            self.id, body_size, end_of_stream
+        );
+
+        let content_type = self.get_http_request_header(CONTENT_TYPE);
+        if !self.is_request_body_transformed(content_type.as_deref()) {
+            return Action::Continue;
+        }
+
+        if let BodyMode::Undecided = self.request_body {
+            // while buffering, body_size is the size of the whole body so far
+            self.check_request_body_size(body_size);
+        }
+
+        match self.request_body {
+            BodyMode::Passthrough => return Action::Continue,
+            BodyMode::Truncate => {
+                self.set_http_request_body(0, body_size, &[]);
+                return Action::Continue;
+            }
+            BodyMode::Rejected => return Action::Pause,
+            _ => {}
+        }
+
+        if !end_of_stream {
+            return Action::Pause;
+        }
+
+        let Some(body) = self.get_http_request_body(0, body_size) else {
+            info!("empty request body, exiting");
+            return Action::Continue;
+        };
+
+        let body = if self.config.content_types.is_json(content_type.as_deref()) {
+            transform_json(&body, |json| {
+                let mut changed = false;
+
+                for json_tx in self
+                    .active_request_transformations()
+                    .filter_map(|tx| tx.json.as_ref())
+                {
+                    changed |= json_tx.resolve(|t| self.render(t)).transform(json);
+                }
+
+                changed
+            })
+        } else {
+            transform_form(&body, |fields| {
+                let mut changed = false;
+
+                for form_tx in self
+                    .active_request_transformations()
+                    .filter_map(|tx| tx.form.as_ref())
+                {
+                    changed |= form_tx.transform(FieldKind::FormField, fields, |t| self.render(t));
+                }
+
+                changed
+            })
         };
 
+        if let Some(body) = body {
+            self.set_http_request_body(0, body_size, &body);
+        }
+
+        Action::Continue
+    }
+
+    fn on_http_response_headers(&mut self, num_headers: usize, end_of_stream: bool) -> Action {
+        info!(
+            "{} on_http_response_headers, num_headers: {}, eof: {}",
+            self.id, num_headers, end_of_stream
+        );
+
+        self.status = self
+            .get_http_response_header(":status")
+            .and_then(|status| status.parse().ok());
+
+        let content_type = self.get_http_response_header(CONTENT_TYPE);
+
+        self.rules = (0..self.config.rules.len())
+            .filter(|i| {
+                self.config.rules[*i].matches(&self.request, self.status, content_type.as_deref())
+            })
+            .collect();
+
+        if self.rules.is_empty() {
+            info!("no rules match the request and response, exiting");
+            return Action::Continue;
+        }
+
+        self.text = content_type.as_deref().is_some_and(is_text_mime_type)
+            || self.is_json_response(content_type.as_deref());
+        self.format = self.body_format(content_type.as_deref());
+
+        if self.format.is_some() {
+            match ContentEncoding::parse(self.get_http_response_header(CONTENT_ENCODING).as_deref())
+            {
+                Ok(encoding) => self.encoding = encoding,
+                Err(e) => {
+                    warn!("{}, not transforming the body", e);
+                    self.body = BodyMode::Passthrough;
+                }
+            }
+
+            let content_length = self
+                .get_http_response_header(CONTENT_LENGTH)
+                .and_then(|value| value.parse().ok());
+
+            if let BodyMode::Passthrough = self.body {
+                // the body is forwarded as-is
+            } else if content_length.is_some_and(|size| self.check_body_size(size)) {
+                match self.body {
+                    BodyMode::Rejected => return Action::Pause,
+                    BodyMode::Truncate => {
+                        self.set_http_response_header(CONTENT_LENGTH, Some("0"));
+                        // an empty body is not validly encoded
+                        self.set_http_response_header(CONTENT_ENCODING, None);
+                    }
+                    _ => {}
+                }
+            } else {
+                info!(
+                    "removing {} header for body transformations",
+                    CONTENT_LENGTH
+                );
+                self.set_http_response_header(CONTENT_LENGTH, None);
+
+                if self.encoding.is_some()
+                    && self.config.on_compressed == CompressedPolicy::Decompress
+                {
+                    info!("removing {} header, body will be decoded", CONTENT_ENCODING);
+                    self.set_http_response_header(CONTENT_ENCODING, None);
+                    self.decompressing = true;
+                }
+            }
+        }
+
+        let mut headers = self.get_http_response_headers();
+        let mut changed = false;
+
+        for rule in self.active_rules() {
+            if let Some(header_tx) = &rule.headers {
+                let header_tx = header_tx.for_status(self.status);
+                changed |= header_tx.transform(FieldKind::Header, &mut headers, |t| self.render(t));
+            }
+        }
+
+        if changed {
+            self.set_http_response_headers(
+                headers
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str()))
+                    .collect(),
+            );
+        }
+
         Action::Continue
     }
 
@@ -117,102 +599,565 @@ impl HttpContext for ResponseTransformerHttp {
             self.id, body_size, end_of_stream
         );
 
-        if let Some(json_tx) = &self.config.json {
-            if !self.is_json_response() {
-                info!("response is not JSON, exiting");
-                return Action::Continue;
-            }
+        let Some(mut format) = self.format else {
+            return Action::Continue;
+        };
 
-            if !end_of_stream {
-                return Action::Pause;
+        if let Some(action) = self.oversize_action(body_size) {
+            return action;
+        }
+
+        if let BodyMode::Undecided = self.body {
+            // compressed bodies are sniffed once decoded, by failing to parse
+            if format == BodyFormat::Json
+                && self.encoding.is_none()
+                && !self.has_json_content_type()
+            {
+                let start = self
+                    .get_http_response_body(0, body_size)
+                    .unwrap_or_default();
+
+                match sniff_json(&start) {
+                    Some(true) => info!("response body looks like JSON"),
+                    Some(false) if self.has_regex_replace() => {
+                        info!("response body does not look like JSON, transforming it as text");
+                        format = BodyFormat::Text;
+                        self.format = Some(format);
+                    }
+                    Some(false) => {
+                        info!("response body does not look like JSON, exiting");
+                        self.body = BodyMode::Passthrough;
+                        return Action::Continue;
+                    }
+                    // only whitespace so far, which can be forwarded as-is
+                    None => return Action::Continue,
+                }
             }
 
-            let Some(body) = self.get_http_response_body(0, body_size) else {
-                info!("empty response body, exiting");
-                return Action::Continue;
+            // compressed bodies are decoded as a whole, and regular expressions
+            // could match across chunks, unless they are applied to each
+            // document of a stream
+            let whole = self.encoding.is_some()
+                || (self.has_regex_replace()
+                    && !matches!(format, BodyFormat::Ndjson | BodyFormat::EventStream));
+            let plan = match format {
+                BodyFormat::Json if !whole => self.stream_plan(),
+                _ => None,
             };
 
-            self.transform_body(json_tx, body);
+            self.body = match plan {
+                Some(plan) => {
+                    info!("streaming response body transformations");
+                    BodyMode::Stream(Box::new(JsonStream::new(plan, self.config.max_body_size)))
+                }
+                None => match self.document_stream(format) {
+                    Some(documents) if !whole => {
+                        info!("streaming response body transformations per document");
+                        BodyMode::Documents(Box::new(documents))
+                    }
+                    _ => BodyMode::Buffer,
+                },
+            };
+        }
+
+        if let BodyMode::Documents(mut documents) =
+            std::mem::replace(&mut self.body, BodyMode::Buffer)
+        {
+            let chunk = self
+                .get_http_response_body(0, body_size)
+                .unwrap_or_default();
+            let mut out = Vec::with_capacity(chunk.len());
+
+            documents.feed(&chunk, &mut out);
+            if end_of_stream {
+                documents.finish(&mut out);
+            }
+
+            self.set_http_response_body(0, body_size, &out);
+            self.body = BodyMode::Documents(documents);
+            return Action::Continue;
+        }
+
+        // the mode has been decided, so anything but a stream is buffered
+        if let BodyMode::Stream(mut stream) = std::mem::replace(&mut self.body, BodyMode::Buffer) {
+            let chunk = self
+                .get_http_response_body(0, body_size)
+                .unwrap_or_default();
+            let mut out = Vec::with_capacity(chunk.len());
+
+            match stream.feed(&chunk, &mut out) {
+                Ok(()) => {
+                    // too much was held back, and the stream now forwards the
+                    // rest of the body as-is
+                    if let Some(size) = stream.oversize() {
+                        self.check_body_size(size);
+
+                        if let BodyMode::Passthrough = self.body {
+                            self.set_http_response_body(0, body_size, &out);
+                            return Action::Continue;
+                        }
+
+                        return self.oversize_action(body_size).unwrap_or(Action::Continue);
+                    }
+
+                    if end_of_stream {
+                        stream.finish(&mut out);
+                    }
+
+                    self.set_http_response_body(0, body_size, &out);
+                    self.body = BodyMode::Stream(stream);
+                    return Action::Continue;
+                }
+                Err(_) => info!("response body is not an object, buffering it"),
+            }
+        }
+
+        // while buffering, body_size is the size of the whole body so far
+        if self.check_body_size(body_size) {
+            return self.oversize_action(body_size).unwrap_or(Action::Continue);
+        }
+
+        if !end_of_stream {
+            return Action::Pause;
         }
 
+        let Some(body) = self.get_http_response_body(0, body_size) else {
+            info!("empty response body, exiting");
+            return Action::Continue;
+        };
+
+        let Some(encoding) = self.encoding else {
+            if let Some(body) = self.transform_body(&body) {
+                self.set_http_response_body(0, body_size, &body);
+            }
+
+            return Action::Continue;
+        };
+
+        let decoded = match decode_body(encoding, &body, self.config.max_body_size) {
+            Ok(decoded) => decoded,
+            Err(DecodeError::Oversize(max)) => {
+                self.check_body_size(max + 1);
+                return self.oversize_action(body_size).unwrap_or(Action::Continue);
+            }
+            Err(e) if self.decompressing => {
+                warn!(
+                    "failed to decode {} response body ({}), rejecting the response",
+                    encoding, e
+                );
+                self.send_http_response(502, vec![], None);
+                self.body = BodyMode::Rejected;
+                return Action::Pause;
+            }
+            Err(e) => {
+                warn!(
+                    "failed to decode {} response body ({}), exiting",
+                    encoding, e
+                );
+                return Action::Continue;
+            }
+        };
+
+        let recompress = self.config.on_compressed == CompressedPolicy::Recompress;
+
+        let body = match (self.transform_body(&decoded), recompress) {
+            (Some(body), true) => match encoding.encode(&body) {
+                Ok(encoded) => encoded,
+                Err(e) => {
+                    error!(
+                        "failed to encode {} response body ({}), exiting",
+                        encoding, e
+                    );
+                    return Action::Continue;
+                }
+            },
+            (Some(body), false) => body,
+            (None, true) => return Action::Continue,
+            (None, false) => decoded,
+        };
+
+        self.set_http_response_body(0, body_size, &body);
+
         Action::Continue
     }
 }
 
 impl ResponseTransformerHttp {
-    fn is_json_response(&self) -> bool {
-        self.get_http_response_header(CONTENT_TYPE)
-            .map_or(false, is_json_mime_type)
+    fn active_rules(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter().map(|i| &self.config.rules[*i])
     }
 
-    fn transform_headers(&self, tx: &Headers) {
-        // https://docs.konghq.com/hub/kong-inc/response-transformer/#order-of-execution
+    fn active_request_transformations(&self) -> impl Iterator<Item = &RequestTransformations> {
+        self.request_rules
+            .iter()
+            .filter_map(|i| self.config.rules[*i].request.as_ref())
+    }
+
+    fn is_request_body_transformed(&self, content_type: Option<&str>) -> bool {
+        match content_type {
+            ct if self.config.content_types.is_json(ct) => self
+                .active_request_transformations()
+                .any(|tx| tx.json.is_some()),
+            Some(ct) if is_form_mime_type(ct) => self
+                .active_request_transformations()
+                .any(|tx| tx.form.is_some()),
+            _ => false,
+        }
+    }
+
+    fn has_json_content_type(&self) -> bool {
+        let content_type = self.get_http_response_header(CONTENT_TYPE);
+        self.config.content_types.is_json(content_type.as_deref())
+    }
+
+    /// Returns whether the response body may be JSON: either its content
+    /// type says so, or the body is sniffed.
+    fn is_json_response(&self, content_type: Option<&str>) -> bool {
+        self.config.content_types.is_json(content_type)
+            || self.config.content_types.sniffs(content_type)
+    }
+
+    /// Returns the format of a response body that the active rules
+    /// transform, if any.
+    fn body_format(&self, content_type: Option<&str>) -> Option<BodyFormat> {
+        if self.active_rules().any(|rule| rule.xml.is_some())
+            && content_type.is_some_and(is_xml_mime_type)
+        {
+            return Some(BodyFormat::Xml);
+        }
 
-        tx.remove.iter().for_each(|name| {
-            if self.get_http_response_header(name).is_some() {
-                info!("removing header: {}", name);
-                self.set_http_response_header(name, None);
+        if self.active_rules().any(|rule| rule.form.is_some())
+            && content_type.is_some_and(is_form_mime_type)
+        {
+            return Some(BodyFormat::Form);
+        }
+
+        let has_json = self.active_rules().any(|rule| rule.json.is_some());
+
+        // regular expressions are applied to each document of a stream
+        if has_json || self.has_regex_replace() {
+            if content_type.is_some_and(is_ndjson_mime_type) {
+                return Some(BodyFormat::Ndjson);
             }
-        });
 
-        tx.rename.iter().for_each(|KeyValue(from, to)| {
-            if let Some(value) = self.get_http_response_header(from) {
-                info!("renaming header {} => {}", from, to);
-                self.set_http_response_header(from, None);
-                self.set_http_response_header(to, Some(value.as_ref()));
+            if content_type.is_some_and(is_event_stream_mime_type) {
+                return Some(BodyFormat::EventStream);
             }
-        });
+        }
+
+        if has_json && self.is_json_response(content_type) {
+            return Some(BodyFormat::Json);
+        }
+
+        if self.has_regex_replace() {
+            return Some(BodyFormat::Text);
+        }
 
-        tx.replace.iter().for_each(|KeyValue(name, value)| {
-            if self.get_http_response_header(name).is_some() {
-                info!("updating header {} value to {}", name, value);
-                self.set_http_response_header(name, Some(value));
+        None
+    }
+
+    /// Returns whether the active rules have `regex_replace` rules that apply
+    /// to the response body.
+    fn has_regex_replace(&self) -> bool {
+        self.text
+            && self
+                .active_rules()
+                .any(|rule| !rule.regex_replace.is_empty())
+    }
+
+    /// Returns the `regex_replace` rules of the active rules that apply to the
+    /// response body.
+    fn regex_replace_rules(&self) -> Vec<RegexReplace> {
+        if !self.text {
+            return vec![];
+        }
+
+        self.active_rules()
+            .flat_map(|rule| rule.regex_replace.iter().cloned())
+            .collect()
+    }
+
+    fn transform_querystring(&self, headers: &mut [(String, String)]) -> bool {
+        let Some((_, path)) = headers.iter_mut().find(|(name, _)| name == ":path") else {
+            return false;
+        };
+
+        let transformed = transform_query(path, |args| {
+            let mut changed = false;
+
+            for query_tx in self
+                .active_request_transformations()
+                .filter_map(|tx| tx.querystring.as_ref())
+            {
+                changed |= query_tx.transform(FieldKind::QueryArg, args, |t| self.render(t));
             }
+
+            changed
         });
 
-        tx.add.iter().for_each(|KeyValue(name, value)| {
-            if self.get_http_response_header(name).is_none() {
-                info!("adding header {} => {}", name, value);
-                self.set_http_response_header(name, Some(value));
+        match transformed {
+            Some(transformed) => {
+                *path = transformed;
+                true
             }
-        });
+            None => false,
+        }
+    }
 
-        tx.append.iter().for_each(|KeyValue(name, value)| {
-            info!("appending header {} => {}", name, value);
-            self.add_http_response_header(name, value);
-        });
+    fn render(&self, template: &Template) -> String {
+        template.render(|var| match var {
+            Variable::RequestHeader(name) => self.request.headers.get(name).cloned(),
+            Variable::RequestMethod => self.request.method.clone(),
+            Variable::RequestPath => self.request.path.clone(),
+            Variable::RequestQuery => self.request.query.clone(),
+            Variable::RequestAuthority => self.request.authority.clone(),
+            Variable::QueryArg(name) => self.request.query_arg(name),
+            Variable::ResponseHeader(name) => self.get_http_response_header(name),
+            Variable::ResponseStatus => self.get_http_response_header(":status"),
+            Variable::Property(path) => self
+                .get_property(path.iter().map(String::as_str).collect())
+                .and_then(|bytes| String::from_utf8(bytes).ok()),
+        })
     }
 
-    fn transform_body(&self, tx: &Json, body: Vec<u8>) {
-        let mut json = match serde_json::from_slice(&body) {
-            Ok(JsonValue::Object(value)) => value,
-            Ok(other) => {
+    /// Applies the `on_oversize` policy if a response body of `size` bytes is
+    /// larger than `max_body_size`. Returns whether it was.
+    fn check_body_size(&mut self, size: usize) -> bool {
+        let Some(max) = self.config.max_body_size else {
+            return false;
+        };
+
+        if size <= max {
+            return false;
+        }
+
+        let policy = match self.config.on_oversize {
+            // without its content-encoding header, the body can't be forwarded
+            // untransformed
+            OversizePolicy::Passthrough if self.decompressing => OversizePolicy::Reject,
+            policy => policy,
+        };
+
+        self.body = match policy {
+            OversizePolicy::Passthrough => {
+                info!(
+                    "response body exceeds max_body_size ({} > {}), forwarding it untransformed",
+                    size, max
+                );
+                BodyMode::Passthrough
+            }
+            OversizePolicy::Reject => {
                 warn!(
-                    "invalid response body type (expected: object, got: {}), exiting",
-                    json::type_name(other)
+                    "response body exceeds max_body_size ({} > {}), rejecting the response",
+                    size, max
                 );
-                return;
+                self.send_http_response(502, vec![], None);
+                BodyMode::Rejected
             }
-            Err(e) => {
-                warn!("response body was invalid JSON ({}), exiting", e);
-                return;
+            OversizePolicy::TruncateHeadersOnly => {
+                info!(
+                    "response body exceeds max_body_size ({} > {}), dropping it",
+                    size, max
+                );
+                BodyMode::Truncate
             }
         };
 
-        if !tx.transform_body(&mut json) {
-            info!("no response body changes were applied");
-            return;
+        true
+    }
+
+    /// Applies the `on_oversize` policy if a request body of `size` bytes is
+    /// larger than `max_body_size`. Returns whether it was.
+    fn check_request_body_size(&mut self, size: usize) -> bool {
+        let Some(max) = self.config.max_body_size else {
+            return false;
+        };
+
+        if size <= max {
+            return false;
         }
 
-        let body = match serde_json::to_vec(&json) {
-            Ok(b) => b,
-            Err(e) => {
-                error!("failed to re-serialize JSON response body ({}), exiting", e);
-                return;
+        self.request_body = match self.config.on_oversize {
+            OversizePolicy::Passthrough => {
+                info!(
+                    "request body exceeds max_body_size ({} > {}), forwarding it untransformed",
+                    size, max
+                );
+                BodyMode::Passthrough
+            }
+            OversizePolicy::Reject => {
+                warn!(
+                    "request body exceeds max_body_size ({} > {}), rejecting the request",
+                    size, max
+                );
+                self.send_http_response(413, vec![], None);
+                BodyMode::Rejected
+            }
+            OversizePolicy::TruncateHeadersOnly => {
+                info!(
+                    "request body exceeds max_body_size ({} > {}), dropping it",
+                    size, max
+                );
+                BodyMode::Truncate
             }
         };
 
-        self.set_http_response_body(0, body.len(), body.as_slice());
+        true
+    }
+
+    /// Returns the action for a body chunk once the body is known to be
+    /// larger than `max_body_size`.
+    fn oversize_action(&self, body_size: usize) -> Option<Action> {
+        match self.body {
+            BodyMode::Passthrough => Some(Action::Continue),
+            BodyMode::Truncate => {
+                self.set_http_response_body(0, body_size, &[]);
+                Some(Action::Continue)
+            }
+            BodyMode::Rejected => Some(Action::Pause),
+            BodyMode::Undecided
+            | BodyMode::Stream(_)
+            | BodyMode::Documents(_)
+            | BodyMode::Buffer => None,
+        }
+    }
+
+    /// Returns the JSON transformations of the active rules that apply to
+    /// the response, with templated values rendered.
+    fn json_transformations(&self) -> Vec<Json> {
+        self.active_rules()
+            .filter_map(|rule| rule.json.as_ref())
+            .map(|json_tx| {
+                json_tx
+                    .for_status(self.status)
+                    .resolve(|t| self.render(t))
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    /// Returns a stream for transforming a body of separate JSON documents,
+    /// if it is one.
+    fn document_stream(&self, format: BodyFormat) -> Option<DocumentStream> {
+        match format {
+            BodyFormat::Ndjson => Some(DocumentStream::Ndjson(NdjsonStream::new(
+                self.json_transformations(),
+                self.regex_replace_rules(),
+                self.config.max_body_size,
+            ))),
+            BodyFormat::EventStream => Some(DocumentStream::Events(EventStream::new(
+                self.json_transformations(),
+                self.regex_replace_rules(),
+                self.config.max_body_size,
+            ))),
+            BodyFormat::Json | BodyFormat::Xml | BodyFormat::Form | BodyFormat::Text => None,
+        }
+    }
+
+    /// Returns a plan for streaming the body transformations of the active
+    /// rules, if none of them need the whole document.
+    fn stream_plan(&self) -> Option<StreamPlan> {
+        StreamPlan::new(&self.json_transformations())
+    }
+
+    /// Returns whether the body transformations of the active rules could
+    /// change a body, without parsing it.
+    fn may_transform(&self, body: &[u8]) -> bool {
+        let transformations: Vec<_> = self
+            .active_rules()
+            .filter_map(|rule| rule.json.as_ref())
+            .map(|json_tx| json_tx.for_status(self.status))
+            .collect();
+
+        let mut keys = vec![];
+
+        for json_tx in &transformations {
+            match json_tx.required_keys() {
+                Some(required) => keys.extend(required),
+                None => return true,
+            }
+        }
+
+        scan::has_member(body, &keys)
+    }
+
+    /// Transforms a complete (decoded) body, returning the new body if it was
+    /// changed.
+    fn transform_body(&self, body: &[u8]) -> Option<Vec<u8>> {
+        let transformed = self.transform_document(body);
+
+        // regular expressions were applied to each document of a stream
+        if let Some(BodyFormat::Ndjson | BodyFormat::EventStream) = self.format {
+            return transformed;
+        }
+
+        let rules = self.regex_replace_rules();
+
+        text::replace_all(&rules, transformed.as_deref().unwrap_or(body)).or(transformed)
+    }
+
+    /// Applies the JSON, XML or form transformations of the active rules to a
+    /// body in that format.
+    fn transform_document(&self, body: &[u8]) -> Option<Vec<u8>> {
+        match self.format {
+            Some(BodyFormat::Xml) => {
+                return transform_xml(body, |doc| {
+                    let mut changed = false;
+
+                    for xml_tx in self.active_rules().filter_map(|rule| rule.xml.as_ref()) {
+                        let xml_tx = xml_tx.for_status(self.status);
+                        changed |= xml_tx.transform(doc, |t| self.render(t));
+                    }
+
+                    changed
+                });
+            }
+            Some(BodyFormat::Form) => {
+                return transform_form(body, |fields| {
+                    let mut changed = false;
+
+                    for form_tx in self.active_rules().filter_map(|rule| rule.form.as_ref()) {
+                        let form_tx = form_tx.for_status(self.status);
+                        changed |=
+                            form_tx.transform(FieldKind::FormField, fields, |t| self.render(t));
+                    }
+
+                    changed
+                });
+            }
+            Some(format @ (BodyFormat::Ndjson | BodyFormat::EventStream)) => {
+                let mut documents = self.document_stream(format)?;
+                let mut out = Vec::with_capacity(body.len());
+
+                documents.feed(body, &mut out);
+                documents.finish(&mut out);
+
+                if out == body {
+                    info!("no body changes were applied");
+                    return None;
+                }
+
+                return Some(out);
+            }
+            Some(BodyFormat::Text) => return None,
+            Some(BodyFormat::Json) | None => {}
+        }
+
+        if !self.may_transform(body) {
+            info!("body has none of the transformed fields, exiting");
+            return None;
+        }
+
+        transform_json(body, |json| {
+            let mut changed = false;
+
+            for json_tx in self.active_rules().filter_map(|rule| rule.json.as_ref()) {
+                let json_tx = json_tx.for_status(self.status);
+                changed |= json_tx.resolve(|t| self.render(t)).transform(json);
+            }
+
+            changed
+        })
     }
 }
 
@@ -221,18 +1166,63 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_json_mime_type_detection() {
-        assert!(is_json_mime_type("application/json"));
-        assert!(is_json_mime_type("APPLICATION/json"));
-        assert!(is_json_mime_type("APPLICATION/JSON"));
-        assert!(is_json_mime_type("application/JSON"));
-        assert!(is_json_mime_type("application/json; charset=utf-8"));
-        assert!(is_json_mime_type("application/problem+json"));
-        assert!(is_json_mime_type("application/problem+JSON"));
-        assert!(is_json_mime_type("application/problem+json; charset=utf-8"));
-
-        assert!(!is_json_mime_type("text/plain"));
-        assert!(!is_json_mime_type("application/not-json"));
-        assert!(!is_json_mime_type("nope/json"));
+    fn test_transform_json_preserves_formatting() {
+        let body = br#"{"z": 1.0, "id": 12345678901234567890123, "a": [1e3, -0.0], "b": 2}"#;
+
+        let body = transform_json(body, |json| {
+            json.as_object_mut().unwrap().shift_remove("b").is_some()
+        });
+
+        assert_eq!(
+            Some(br#"{"z": 1.0, "id": 12345678901234567890123, "a": [1e3, -0.0]}"#.to_vec()),
+            body
+        );
+    }
+
+    #[test]
+    fn test_transform_query_keeps_encoding() {
+        let remove = |name: &str| {
+            let name = name.to_owned();
+            move |args: &mut Vec<(String, String)>| {
+                let len = args.len();
+                args.retain(|(n, _)| *n != name);
+                args.len() != len
+            }
+        };
+
+        assert_eq!(
+            Some("/a?sig=x%20y%2F&flag&b=1+2".to_owned()),
+            transform_query("/a?sig=x%20y%2F&flag&drop=1&b=1+2", remove("drop"))
+        );
+        assert_eq!(
+            Some("/a".to_owned()),
+            transform_query("/a?drop=1", remove("drop"))
+        );
+        assert_eq!(None, transform_query("/a?flag", remove("drop")));
+    }
+
+    #[test]
+    fn test_decode_body_oversize() {
+        let body = ContentEncoding::Gzip.encode(&vec![b' '; 1 << 24]).unwrap();
+        assert!(body.len() < 1 << 16);
+
+        assert!(matches!(
+            decode_body(ContentEncoding::Gzip, &body, Some(1 << 16)),
+            Err(DecodeError::Oversize(max)) if max == 1 << 16
+        ));
+        assert_eq!(
+            Some(1 << 24),
+            decode_body(ContentEncoding::Gzip, &body, Some(1 << 24))
+                .ok()
+                .map(|decoded| decoded.len())
+        );
+    }
+
+    #[test]
+    fn test_decode_body_invalid() {
+        assert!(matches!(
+            decode_body(ContentEncoding::Gzip, br#"{"id": 1}"#, Some(1 << 16)),
+            Err(DecodeError::Invalid(_))
+        ));
     }
 }
@@ -0,0 +1,150 @@
+//! `application/x-www-form-urlencoded` encoding, used for query strings and
+//! form bodies.
+//!
+//! https://url.spec.whatwg.org/#application/x-www-form-urlencoded
+
+/// Decodes a percent-encoded component. `+` decodes to a space, and invalid
+/// escape sequences are left as they are.
+pub(crate) fn decode(s: &str) -> String {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let digit = |offset| bytes.get(i + offset).and_then(|b| hex(*b));
+
+                match (digit(1), digit(2)) {
+                    (Some(hi), Some(lo)) => {
+                        out.push(hi << 4 | lo);
+                        i += 2;
+                    }
+                    _ => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Percent-encodes a component, encoding spaces as `+`.
+pub(crate) fn encode(s: &str) -> String {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut out = String::with_capacity(s.len());
+
+    for b in s.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => {
+                out.push(b as char)
+            }
+            b' ' => out.push('+'),
+            b => {
+                out.push('%');
+                out.push(HEX[(b >> 4) as usize] as char);
+                out.push(HEX[(b & 0xf) as usize] as char);
+            }
+        }
+    }
+
+    out
+}
+
+/// Parses an encoded string into decoded name/value pairs. Names without a
+/// value get an empty value.
+pub(crate) fn parse(s: &str) -> Vec<(String, String)> {
+    s.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+/// Encodes name/value pairs that were parsed from `original` and then
+/// transformed. Pairs that are still present keep their original encoding,
+/// so only the changed ones are encoded again.
+pub(crate) fn reserialize(original: &str, pairs: &[(String, String)]) -> String {
+    let mut raw: Vec<Option<(&str, (String, String))>> = original
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| Some((pair, parse(pair).remove(0))))
+        .collect();
+
+    pairs
+        .iter()
+        .map(|pair| {
+            let found = raw
+                .iter_mut()
+                .find(|r| r.as_ref().is_some_and(|(_, decoded)| decoded == pair));
+
+            match found.and_then(Option::take) {
+                Some((encoded, _)) => encoded.to_owned(),
+                None => format!("{}={}", encode(&pair.0), encode(&pair.1)),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!("x y/z", decode("x+y%2Fz"));
+        assert_eq!("é", decode("%C3%A9"));
+        assert_eq!("%zz%4", decode("%zz%4"));
+        assert_eq!("100%", decode("100%"));
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!("a+b%2Fc%3D%26", encode("a b/c=&"));
+        assert_eq!("%C3%A9", encode("é"));
+        assert_eq!("safe-._*", encode("safe-._*"));
+    }
+
+    #[test]
+    fn test_parse_serialize() {
+        let pairs = parse("a=1&b&&c=x+y%26z&a=2");
+
+        assert_eq!(
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "".to_string()),
+                ("c".to_string(), "x y&z".to_string()),
+                ("a".to_string(), "2".to_string()),
+            ],
+            pairs
+        );
+
+        assert_eq!("a=1&b=&c=x+y%26z&a=2", reserialize("", &pairs));
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn test_reserialize() {
+        let original = "token=a%2Fb&scope=read%20write&flag&scope=x";
+        let mut pairs = parse(original);
+
+        pairs.remove(0);
+        pairs[2].1 = "y/z".to_string();
+        pairs.push(("state".to_string(), "a b".to_string()));
+
+        assert_eq!(
+            "scope=read%20write&flag&scope=y%2Fz&state=a+b",
+            reserialize(original, &pairs)
+        );
+    }
+}
@@ -6,13 +6,19 @@ use serde_json::{self, json, Value};
 
 //use serde_json::{Result as JsonResult, Value as JsonValue};
 
+pub(crate) type JsonMap = serde_json::Map<String, Value>;
+
 #[derive(Debug, Clone, Eq, PartialEq, Default, Deserialize)]
-#[serde(try_from = "&str")]
+#[serde(try_from = "String")]
 pub(crate) enum Cast {
     Number,
     Boolean,
     #[default]
     String,
+    Object,
+    Array,
+    Null,
+    Json,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
@@ -27,35 +33,77 @@ impl std::fmt::Display for InvalidCastType {
 impl std::error::Error for InvalidCastType {}
 
 impl TryFrom<&str> for Cast {
-    type Error = String;
+    type Error = InvalidCastType;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value.to_lowercase().as_str() {
             "number" => Ok(Cast::Number),
             "boolean" => Ok(Cast::Boolean),
             "string" => Ok(Cast::String),
-            _ => Err("no".into()),
+            "object" => Ok(Cast::Object),
+            "array" => Ok(Cast::Array),
+            "null" => Ok(Cast::Null),
+            "json" => Ok(Cast::Json),
+            _ => Err(InvalidCastType(value.to_owned())),
         }
     }
 }
 
+impl TryFrom<String> for Cast {
+    type Error = InvalidCastType;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Cast::try_from(value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct InvalidCastValue {
+    cast: Cast,
+    value: String,
+}
+
+impl std::fmt::Display for InvalidCastValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {} value: {:?}", self.cast.name(), self.value)
+    }
+}
+
+impl std::error::Error for InvalidCastValue {}
+
 impl Cast {
-    pub(crate) fn convert(&self, s: String) -> Value {
+    pub(crate) fn name(&self) -> &'static str {
         match self {
-            Self::String => Value::String(escape(&s)),
-            Self::Number => match s.parse::<serde_json::Number>() {
-                Ok(n) => Value::Number(n),
-                Err(_) => Value::String(s),
-            },
-            Self::Boolean => {
-                if &s == "true" {
-                    json!(true)
-                } else {
-                    json!(false)
-                }
-            }
+            Self::Number => "number",
+            Self::Boolean => "boolean",
+            Self::String => "string",
+            Self::Object => "object",
+            Self::Array => "array",
+            Self::Null => "null",
+            Self::Json => "json",
         }
     }
+
+    pub(crate) fn convert(&self, s: String) -> Result<Value, InvalidCastValue> {
+        let value = match self {
+            Self::String => Some(Value::String(escape(&s))),
+            Self::Number => s.parse::<serde_json::Number>().ok().map(Value::Number),
+            Self::Boolean => match s.as_str() {
+                "true" => Some(json!(true)),
+                "false" => Some(json!(false)),
+                _ => None,
+            },
+            Self::Object => serde_json::from_str(&s).ok().filter(Value::is_object),
+            Self::Array => serde_json::from_str(&s).ok().filter(Value::is_array),
+            Self::Null => (s == "null").then_some(Value::Null),
+            Self::Json => serde_json::from_str(&s).ok(),
+        };
+
+        value.ok_or_else(|| InvalidCastValue {
+            cast: self.clone(),
+            value: s,
+        })
+    }
 }
 
 pub(crate) fn type_name(v: Value) -> &'static str {
@@ -86,6 +134,64 @@ fn escape(s: &str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_cast_try_from() {
+        assert_eq!(Ok(Cast::Object), Cast::try_from("object"));
+        assert_eq!(Ok(Cast::Array), Cast::try_from("Array"));
+        assert_eq!(Ok(Cast::Null), Cast::try_from("null"));
+        assert_eq!(Ok(Cast::Json), Cast::try_from("JSON"));
+        assert!(Cast::try_from("nope").is_err());
+    }
+
+    #[test]
+    fn test_cast_convert() {
+        let ok = |cast: Cast, s: &str| cast.convert(s.to_string()).unwrap();
+
+        assert_eq!(json!("a\\\\b"), ok(Cast::String, r"a\b"));
+        assert_eq!(json!(1), ok(Cast::Number, "1"));
+        assert_eq!(json!(1.5), ok(Cast::Number, "1.5"));
+        assert_eq!(json!(true), ok(Cast::Boolean, "true"));
+        assert_eq!(json!(false), ok(Cast::Boolean, "false"));
+        assert_eq!(
+            json!({ "self": "/x" }),
+            ok(Cast::Object, r#"{"self":"/x"}"#)
+        );
+        assert_eq!(json!([1, "a"]), ok(Cast::Array, r#"[1,"a"]"#));
+        assert_eq!(Value::Null, ok(Cast::Null, "null"));
+        assert_eq!(json!(1), ok(Cast::Json, "1"));
+        assert_eq!(json!(1.0), ok(Cast::Json, "1.0"));
+        assert_eq!(json!({ "a": [true] }), ok(Cast::Json, r#"{"a":[true]}"#));
+    }
+
+    #[test]
+    fn test_cast_convert_invalid() {
+        for (cast, s) in [
+            (Cast::Number, "abc"),
+            (Cast::Boolean, "yes"),
+            (Cast::Boolean, "TRUE"),
+            (Cast::Object, "[1]"),
+            (Cast::Array, "{}"),
+            (Cast::Null, "nil"),
+            (Cast::Json, "nope"),
+        ] {
+            assert_eq!(
+                Err(InvalidCastValue {
+                    cast: cast.clone(),
+                    value: s.to_string()
+                }),
+                cast.convert(s.to_string())
+            );
+        }
+
+        assert_eq!(
+            r#"Invalid number value: "abc""#,
+            Cast::Number
+                .convert("abc".to_string())
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
     #[test]
     fn test_escape() {
         const A: u8 = 97;
@@ -0,0 +1,381 @@
+//! Rule match conditions.
+//!
+//! A `match` block restricts a rule to certain requests and responses:
+//!
+//! ```json
+//! {
+//!   "path": ["/api/**"],
+//!   "path_regex": ["^/v[0-9]+/users$"],
+//!   "method": ["GET", "HEAD"],
+//!   "host": ["*.example.com"],
+//!   "headers": ["x-debug", "x-tenant:acme"],
+//!   "query": ["debug", "format:json"],
+//!   "content_type": ["application/*+json"]
+//! }
+//! ```
+//!
+//! Every condition that is present must match, and a condition matches if any
+//! of its entries match, except for `headers` and `query`, of which every
+//! entry must match. `path` and `path_regex` together form a single condition.
+//! In globs, `*` and `?` do not match across a `/` (or a `.` in host names)
+//! while `**` matches anything.
+//!
+//! Header and query entries are either a name, which matches if the request
+//! header or query string argument is present, or a `<name>:<value>` pair,
+//! which matches if it has exactly that value. Header names are
+//! case-insensitive, query string argument names are not.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+use crate::request::RequestInfo;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidMatch {
+    field: &'static str,
+    pattern: String,
+    reason: String,
+}
+
+impl fmt::Display for InvalidMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid {} pattern {:?}: {}",
+            self.field, self.pattern, self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidMatch {}
+
+#[derive(Deserialize, Default, PartialEq, Eq, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct MatchInput {
+    path: Vec<String>,
+    path_regex: Vec<String>,
+    method: Vec<String>,
+    host: Vec<String>,
+    headers: Vec<String>,
+    query: Vec<String>,
+    content_type: Vec<String>,
+}
+
+/// Compiles a glob into an anchored regex. `*` and `?` do not match
+/// `separator`, `**` matches any sequence of characters.
+pub(crate) fn glob(
+    pattern: &str,
+    separator: char,
+    case_insensitive: bool,
+) -> Result<Regex, regex::Error> {
+    let not_separator = format!("[^{}]", regex::escape(&separator.to_string()));
+
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            }
+            '*' => {
+                re.push_str(&not_separator);
+                re.push('*');
+            }
+            '?' => re.push_str(&not_separator),
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    re.push('$');
+
+    RegexBuilder::new(&re)
+        .case_insensitive(case_insensitive)
+        .build()
+}
+
+/// Compiled match conditions. An empty `Match` matches everything.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Match {
+    path: Vec<Regex>,
+    method: Vec<String>,
+    host: Vec<Regex>,
+    headers: Vec<(String, Option<String>)>,
+    query: Vec<(String, Option<String>)>,
+    content_type: Vec<Regex>,
+}
+
+/// Parses `<name>` and `<name>:<value>` entries.
+fn name_values(
+    field: &'static str,
+    entries: &[String],
+) -> Result<Vec<(String, Option<String>)>, InvalidMatch> {
+    entries
+        .iter()
+        .map(|entry| {
+            let (name, value) = match entry.split_once(':') {
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (entry.as_str(), None),
+            };
+
+            if name.is_empty() {
+                return Err(InvalidMatch {
+                    field,
+                    pattern: entry.clone(),
+                    reason: "empty name".to_owned(),
+                });
+            }
+
+            Ok((name.to_owned(), value))
+        })
+        .collect()
+}
+
+fn matches_name_values<F>(conditions: &[(String, Option<String>)], get: F) -> bool
+where
+    F: Fn(&str) -> Option<String>,
+{
+    conditions
+        .iter()
+        .all(|(name, value)| match (get(name), value) {
+            (Some(found), Some(value)) => found == *value,
+            (found, None) => found.is_some(),
+            (None, Some(_)) => false,
+        })
+}
+
+impl TryFrom<MatchInput> for Match {
+    type Error = InvalidMatch;
+
+    fn try_from(input: MatchInput) -> Result<Self, Self::Error> {
+        fn compile<F>(
+            field: &'static str,
+            patterns: &[String],
+            build: F,
+        ) -> Result<Vec<Regex>, InvalidMatch>
+        where
+            F: Fn(&str) -> Result<Regex, regex::Error>,
+        {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    build(pattern).map_err(|e| InvalidMatch {
+                        field,
+                        pattern: pattern.clone(),
+                        reason: e.to_string(),
+                    })
+                })
+                .collect()
+        }
+
+        let mut path = compile("path", &input.path, |p| glob(p, '/', false))?;
+        path.extend(compile("path_regex", &input.path_regex, Regex::new)?);
+
+        let mut headers = name_values("headers", &input.headers)?;
+        for (name, _) in headers.iter_mut() {
+            *name = name.to_lowercase();
+        }
+
+        Ok(Match {
+            path,
+            method: input.method,
+            host: compile("host", &input.host, |p| glob(p, '.', true))?,
+            headers,
+            query: name_values("query", &input.query)?,
+            content_type: compile("content_type", &input.content_type, |p| glob(p, '/', true))?,
+        })
+    }
+}
+
+impl Match {
+    /// Returns whether there are no conditions.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.path.is_empty()
+            && self.method.is_empty()
+            && self.host.is_empty()
+            && self.headers.is_empty()
+            && self.query.is_empty()
+            && self.content_type.is_empty()
+    }
+
+    /// Returns the names of the request headers that conditions refer to.
+    pub(crate) fn request_headers(&self) -> impl Iterator<Item = &String> {
+        self.headers.iter().map(|(name, _)| name)
+    }
+
+    /// Returns whether there are conditions on the response.
+    pub(crate) fn has_response_conditions(&self) -> bool {
+        !self.content_type.is_empty()
+    }
+
+    /// Returns whether the conditions on the request match.
+    pub(crate) fn matches_request(&self, request: &RequestInfo) -> bool {
+        let method = request.method.as_deref();
+
+        any(&self.path, request.path.as_deref())
+            && any(&self.host, request.host().as_deref())
+            && (self.method.is_empty()
+                || method.is_some_and(|m| self.method.iter().any(|x| x.eq_ignore_ascii_case(m))))
+            && matches_name_values(&self.headers, |name| request.headers.get(name).cloned())
+            && matches_name_values(&self.query, |name| request.query_arg(name))
+    }
+
+    /// Returns whether the conditions match a request and the content type of
+    /// its response.
+    pub(crate) fn matches(&self, request: &RequestInfo, content_type: Option<&str>) -> bool {
+        // compare the type, subtype and suffix only, ignoring any parameters
+        let content_type = content_type
+            .and_then(|ct| ct.parse::<mime::Mime>().ok())
+            .map(|mt| mt.essence_str().to_owned());
+
+        self.matches_request(request) && any(&self.content_type, content_type.as_deref())
+    }
+}
+
+fn any(patterns: &[Regex], value: Option<&str>) -> bool {
+    patterns.is_empty() || value.is_some_and(|v| patterns.iter().any(|p| p.is_match(v)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matcher(input: serde_json::Value) -> Match {
+        Match::try_from(serde_json::from_value::<MatchInput>(input).unwrap()).unwrap()
+    }
+
+    fn request(method: &str, path: &str, host: &str) -> RequestInfo {
+        RequestInfo {
+            method: Some(method.to_owned()),
+            path: Some(path.to_owned()),
+            authority: Some(host.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_glob() {
+        let path = |p: &str| glob(p, '/', false).unwrap();
+
+        assert!(path("/api/*").is_match("/api/users"));
+        assert!(!path("/api/*").is_match("/api/users/1"));
+        assert!(path("/api/**").is_match("/api/users/1"));
+        assert!(path("/api/*/?").is_match("/api/users/1"));
+        assert!(!path("/api/*/?").is_match("/api/users/12"));
+        assert!(path("/a.b+c").is_match("/a.b+c"));
+        assert!(!path("/a.b").is_match("/axb"));
+        assert!(!path("/API").is_match("/api"));
+
+        let host = glob("*.example.com", '.', true).unwrap();
+        assert!(host.is_match("API.example.com"));
+        assert!(!host.is_match("a.b.example.com"));
+        assert!(!host.is_match("example.com"));
+    }
+
+    #[test]
+    fn test_match_empty() {
+        let m = Match::default();
+        assert!(m.is_empty());
+        assert!(!matcher(json!({ "query": ["debug"] })).is_empty());
+
+        assert!(m.matches(&RequestInfo::default(), None));
+        assert!(m.matches(&request("GET", "/", "example.com"), Some("text/plain")));
+    }
+
+    #[test]
+    fn test_match_request() {
+        let m = matcher(json!({
+            "path": ["/api/**"],
+            "path_regex": ["^/v[0-9]+/users$"],
+            "method": ["get", "HEAD"],
+            "host": ["*.example.com"]
+        }));
+
+        assert!(m.matches(&request("GET", "/api/users/1", "api.example.com"), None));
+        assert!(m.matches(&request("HEAD", "/v2/users", "api.example.com"), None));
+        assert!(!m.matches(&request("POST", "/api/users", "api.example.com"), None));
+        assert!(!m.matches(&request("GET", "/v2/users/1", "api.example.com"), None));
+        assert!(!m.matches(&request("GET", "/api/users", "example.com"), None));
+        assert!(!m.matches(&RequestInfo::default(), None));
+    }
+
+    #[test]
+    fn test_match_headers() {
+        let m = matcher(json!({ "headers": ["X-Debug", "x-tenant:acme"] }));
+        assert_eq!(
+            vec!["x-debug", "x-tenant"],
+            m.request_headers().collect::<Vec<_>>()
+        );
+
+        let mut req = RequestInfo::default();
+        req.headers.insert("x-debug".to_owned(), String::new());
+        assert!(!m.matches(&req, None));
+
+        req.headers
+            .insert("x-tenant".to_owned(), "other".to_owned());
+        assert!(!m.matches(&req, None));
+
+        req.headers.insert("x-tenant".to_owned(), "acme".to_owned());
+        assert!(m.matches(&req, None));
+
+        // every entry must match
+        req.headers.remove("x-debug");
+        assert!(!m.matches(&req, None));
+    }
+
+    #[test]
+    fn test_match_query() {
+        let m = matcher(json!({ "query": ["debug", "format:json"] }));
+
+        let matches = |query: &str| {
+            let req = RequestInfo {
+                query: Some(query.to_owned()),
+                ..Default::default()
+            };
+            m.matches(&req, None)
+        };
+
+        assert!(matches("debug&format=json"));
+        assert!(matches("format=json&debug=1"));
+        assert!(!matches("format=json"));
+        assert!(!matches("debug&format=xml"));
+        assert!(!matches("DEBUG&format=json"));
+        assert!(!m.matches(&RequestInfo::default(), None));
+    }
+
+    #[test]
+    fn test_match_content_type() {
+        let m = matcher(json!({ "content_type": ["application/json", "*/*+json"] }));
+        let req = RequestInfo::default();
+
+        assert!(m.matches(&req, Some("application/json")));
+        assert!(m.matches(&req, Some("Application/JSON; charset=utf-8")));
+        assert!(m.matches(&req, Some("application/problem+json")));
+        assert!(!m.matches(&req, Some("text/plain")));
+        assert!(!m.matches(&req, Some("not a content type")));
+        assert!(!m.matches(&req, None));
+    }
+
+    #[test]
+    fn test_match_invalid() {
+        for (input, expect) in [
+            (
+                json!({ "path_regex": ["^/a(b$"] }),
+                r#"Invalid path_regex pattern "^/a(b$""#,
+            ),
+            (
+                json!({ "headers": [":value"] }),
+                r#"Invalid headers pattern ":value": empty name"#,
+            ),
+        ] {
+            let input: MatchInput = serde_json::from_value(input).unwrap();
+            let err = Match::try_from(input).unwrap_err().to_string();
+            assert!(err.starts_with(expect), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! RFC 7396 JSON Merge Patch.
+//!
+//! https://www.rfc-editor.org/rfc/rfc7396
+
+use crate::json::JsonMap;
+use serde_json::Value as JsonValue;
+
+/// Merges `patch` into `target`, returning whether `target` was changed.
+///
+/// `null` members of the patch delete the corresponding member of the target,
+/// objects are merged recursively, and any other value replaces the target.
+pub(crate) fn merge_patch(target: &mut JsonValue, patch: &JsonValue) -> bool {
+    let JsonValue::Object(patch) = patch else {
+        if target == patch {
+            return false;
+        }
+
+        *target = patch.clone();
+        return true;
+    };
+
+    let mut changed = false;
+
+    if !target.is_object() {
+        *target = JsonValue::Object(JsonMap::new());
+        changed = true;
+    }
+
+    let map = target.as_object_mut().expect("target is an object");
+
+    for (key, value) in patch {
+        if value.is_null() {
+            changed |= map.shift_remove(key).is_some();
+            continue;
+        }
+
+        match map.get_mut(key) {
+            Some(found) => changed |= merge_patch(found, value),
+            None => {
+                let mut new = JsonValue::Null;
+                merge_patch(&mut new, value);
+                map.insert(key.clone(), new);
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge_patch_rfc_examples() {
+        // https://www.rfc-editor.org/rfc/rfc7396#appendix-A
+        let cases = [
+            (json!({"a":"b"}), json!({"a":"c"}), json!({"a":"c"})),
+            (json!({"a":"b"}), json!({"b":"c"}), json!({"a":"b","b":"c"})),
+            (json!({"a":"b"}), json!({"a":null}), json!({})),
+            (
+                json!({"a":"b","b":"c"}),
+                json!({"a":null}),
+                json!({"b":"c"}),
+            ),
+            (json!({"a":["b"]}), json!({"a":"c"}), json!({"a":"c"})),
+            (json!({"a":"c"}), json!({"a":["b"]}), json!({"a":["b"]})),
+            (
+                json!({"a":{"b":"c"}}),
+                json!({"a":{"b":"d","c":null}}),
+                json!({"a":{"b":"d"}}),
+            ),
+            (json!({"a":[{"b":"c"}]}), json!({"a":[1]}), json!({"a":[1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a":"b"}), json!(["c"]), json!(["c"])),
+            (json!({"a":"foo"}), json!(null), json!(null)),
+            (json!({"a":"foo"}), json!("bar"), json!("bar")),
+            (json!({"e":null}), json!({"a":1}), json!({"e":null,"a":1})),
+            (json!([1, 2]), json!({"a":"b","c":null}), json!({"a":"b"})),
+            (
+                json!({}),
+                json!({"a":{"bb":{"ccc":null}}}),
+                json!({"a":{"bb":{}}}),
+            ),
+        ];
+
+        for (mut target, patch, expect) in cases {
+            merge_patch(&mut target, &patch);
+            assert_eq!(expect, target, "patch: {}", patch);
+        }
+    }
+
+    #[test]
+    fn test_merge_patch_changed() {
+        let mut target = json!({ "a": { "b": 1 }, "c": 2 });
+
+        assert!(!merge_patch(&mut target, &json!({ "a": { "b": 1 } })));
+        assert!(!merge_patch(&mut target, &json!({ "d": null })));
+        assert!(merge_patch(&mut target, &json!({ "a": { "b": 2 } })));
+        assert!(merge_patch(&mut target, &json!({ "c": null })));
+        assert!(merge_patch(
+            &mut target,
+            &json!({ "meta": { "version": 2 } })
+        ));
+
+        assert_eq!(target, json!({ "a": { "b": 2 }, "meta": { "version": 2 } }));
+    }
+}
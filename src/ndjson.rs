@@ -0,0 +1,345 @@
+//! Newline-delimited JSON bodies.
+//!
+//! `application/x-ndjson` and `application/jsonl` bodies are transformed line
+//! by line as chunks arrive, so that long-running streams are never buffered
+//! as a whole. Each line is transformed as a separate document, and lines
+//! that are blank, not a JSON object or array, or larger than `max_body_size`
+//! are forwarded as they are. A line that grows larger than `max_body_size`
+//! is forwarded as it arrives instead of being buffered. `regex_replace`
+//! rules are applied to each line too, after the JSON transformations, so
+//! they do not match across lines.
+
+use log::*;
+use serde_json::Value as JsonValue;
+
+use crate::scan;
+use crate::splice;
+use crate::text::{self, RegexReplace};
+use crate::types::Json;
+
+/// JSON transformations and `regex_replace` rules applied to many small,
+/// separate documents, such as the lines of an NDJSON body or the data of
+/// server-sent events.
+pub(crate) struct DocumentTransform {
+    transformations: Vec<Json>,
+    regex_replace: Vec<RegexReplace>,
+    /// Member names of which a document must have at least one to be
+    /// changed, or `None` if any document could be.
+    required_keys: Option<Vec<String>>,
+    /// The largest document that is transformed.
+    max_size: Option<usize>,
+}
+
+impl DocumentTransform {
+    pub(crate) fn new(
+        transformations: Vec<Json>,
+        regex_replace: Vec<RegexReplace>,
+        max_size: Option<usize>,
+    ) -> Self {
+        let required_keys = transformations.iter().map(Json::required_keys).try_fold(
+            vec![],
+            |mut keys, required| {
+                keys.extend(required?.into_iter().map(str::to_owned));
+                Some(keys)
+            },
+        );
+
+        DocumentTransform {
+            transformations,
+            regex_replace,
+            required_keys,
+            max_size,
+        }
+    }
+
+    /// Transforms a document, returning the new document if it was changed.
+    /// Documents that are blank or larger than `max_size` are left as they
+    /// are, and so are those that are not a JSON object or array unless a
+    /// `regex_replace` rule matches.
+    pub(crate) fn apply(&self, doc: &[u8]) -> Option<Vec<u8>> {
+        if doc.iter().all(u8::is_ascii_whitespace) {
+            return None;
+        }
+
+        if self.exceeds_max_size(doc.len()) {
+            info!(
+                "document exceeds max_body_size ({} bytes), forwarding it as-is",
+                doc.len()
+            );
+            return None;
+        }
+
+        let transformed = self.apply_json(doc);
+
+        text::replace_all(&self.regex_replace, transformed.as_deref().unwrap_or(doc))
+            .or(transformed)
+    }
+
+    fn apply_json(&self, doc: &[u8]) -> Option<Vec<u8>> {
+        if let Some(keys) = &self.required_keys {
+            let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+            if !scan::has_member(doc, &keys) {
+                return None;
+            }
+        }
+
+        let mut json = match serde_json::from_slice(doc) {
+            Ok(value @ (JsonValue::Object(_) | JsonValue::Array(_))) => value,
+            Ok(_) => return None,
+            Err(e) => {
+                info!("document is not JSON ({}), forwarding it as-is", e);
+                return None;
+            }
+        };
+
+        let mut changed = false;
+        for json_tx in &self.transformations {
+            changed |= json_tx.transform(&mut json);
+        }
+
+        if !changed {
+            return None;
+        }
+
+        match splice::splice(doc, &json) {
+            Ok(doc) => Some(doc),
+            Err(e) => {
+                error!(
+                    "failed to re-serialize document ({}), forwarding it as-is",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns whether a document of `size` bytes is too large to be
+    /// transformed.
+    pub(crate) fn exceeds_max_size(&self, size: usize) -> bool {
+        self.max_size.is_some_and(|max| size > max)
+    }
+}
+
+pub(crate) struct NdjsonStream {
+    transform: DocumentTransform,
+    /// The incomplete last line of the chunks so far.
+    pending: Vec<u8>,
+    /// Whether the incomplete last line is larger than `max_body_size`, so
+    /// the rest of it is forwarded as it arrives.
+    oversize: bool,
+}
+
+impl NdjsonStream {
+    pub(crate) fn new(
+        transformations: Vec<Json>,
+        regex_replace: Vec<RegexReplace>,
+        max_size: Option<usize>,
+    ) -> Self {
+        NdjsonStream {
+            transform: DocumentTransform::new(transformations, regex_replace, max_size),
+            pending: vec![],
+            oversize: false,
+        }
+    }
+
+    /// Transforms the complete lines of a chunk and writes them to `out`. The
+    /// incomplete last line is kept until the next chunk, unless it is too
+    /// large to be transformed.
+    pub(crate) fn feed(&mut self, mut chunk: &[u8], out: &mut Vec<u8>) {
+        if self.oversize {
+            let Some(end) = chunk.iter().position(|b| *b == b'\n') else {
+                out.extend_from_slice(chunk);
+                return;
+            };
+
+            out.extend_from_slice(&chunk[..=end]);
+            chunk = &chunk[end + 1..];
+            self.oversize = false;
+        }
+
+        self.pending.extend_from_slice(chunk);
+
+        if let Some(end) = self.pending.iter().rposition(|b| *b == b'\n') {
+            let rest = self.pending.split_off(end + 1);
+            let complete = std::mem::replace(&mut self.pending, rest);
+
+            for line in complete.split_inclusive(|b| *b == b'\n') {
+                self.write_line(line, out);
+            }
+        }
+
+        if self.transform.exceeds_max_size(self.pending.len()) {
+            info!(
+                "line exceeds max_body_size ({} bytes so far), forwarding it as-is",
+                self.pending.len()
+            );
+            out.append(&mut self.pending);
+            self.oversize = true;
+        }
+    }
+
+    /// Transforms the last line, which has no line terminator.
+    pub(crate) fn finish(&mut self, out: &mut Vec<u8>) {
+        let line = std::mem::take(&mut self.pending);
+
+        if !line.is_empty() {
+            self.write_line(&line, out);
+        }
+    }
+
+    fn write_line(&self, line: &[u8], out: &mut Vec<u8>) {
+        let content = line
+            .strip_suffix(b"\n")
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .unwrap_or(line);
+
+        match self.transform.apply(content) {
+            Some(transformed) => {
+                out.extend_from_slice(&transformed);
+                out.extend_from_slice(&line[content.len()..]);
+            }
+            None => out.extend_from_slice(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::JsonPath;
+    use std::convert::TryFrom;
+
+    fn stream(remove: &[&str]) -> NdjsonStream {
+        NdjsonStream::new(
+            vec![Json {
+                remove: remove
+                    .iter()
+                    .map(|path| JsonPath::try_from(*path).unwrap())
+                    .collect(),
+                ..Default::default()
+            }],
+            vec![],
+            Some(32),
+        )
+    }
+
+    fn feed(stream: &mut NdjsonStream, chunks: &[&str]) -> Vec<String> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                let mut out = vec![];
+                stream.feed(chunk.as_bytes(), &mut out);
+                String::from_utf8(out).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks() {
+        let mut stream = stream(&["secret"]);
+
+        let out = feed(
+            &mut stream,
+            &[
+                r#"{"id": 1, "secret": "a"}"#,
+                "\n{\"id\": 2, \"sec",
+                "ret\": \"b\"}\r\n\n[{\"secret\": 1}]\n",
+                r#"{"id": 3, "secret": "c"}"#,
+            ],
+        );
+
+        assert_eq!(
+            vec!["", "{\"id\": 1}\n", "{\"id\": 2}\r\n\n[{}]\n", ""],
+            out
+        );
+
+        let mut out = vec![];
+        stream.finish(&mut out);
+        assert_eq!(br#"{"id": 3}"#.to_vec(), out);
+    }
+
+    #[test]
+    fn test_untransformed_lines() {
+        let mut stream = stream(&["secret"]);
+        let input = "{\"id\": 1}\n  \nnot json\n\"secret\"\n{\"secret\": \n";
+
+        assert_eq!(vec![input], feed(&mut stream, &[input]));
+
+        let mut out = vec![];
+        stream.finish(&mut out);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_required_keys() {
+        assert_eq!(
+            Some(vec!["a".to_string(), "b".to_string()]),
+            stream(&["a", "x.b"]).transform.required_keys
+        );
+
+        let mut json = Json::default();
+        json.add
+            .push((JsonPath::try_from("a").unwrap(), JsonValue::Null));
+        assert_eq!(
+            None,
+            DocumentTransform::new(vec![json], vec![], None).required_keys
+        );
+    }
+
+    #[test]
+    fn test_regex_replace() {
+        let mut stream = NdjsonStream::new(
+            vec![Json {
+                remove: vec![JsonPath::try_from("secret").unwrap()],
+                ..Default::default()
+            }],
+            vec![RegexReplace::try_from(r"\.internal\b => .example.com").unwrap()],
+            None,
+        );
+
+        // each line is matched separately, after the JSON transformations
+        let out = feed(
+            &mut stream,
+            &[
+                "{\"host\": \"a.internal\", \"secret\": \"b.internal\"}\nc.inter",
+                "nal\n{\"id\": 1}\n",
+            ],
+        );
+
+        assert_eq!(
+            vec![
+                "{\"host\": \"a.example.com\"}\n",
+                "c.example.com\n{\"id\": 1}\n"
+            ],
+            out
+        );
+    }
+
+    #[test]
+    fn test_oversized_lines() {
+        let mut stream = stream(&["secret"]);
+
+        let out = feed(
+            &mut stream,
+            &[
+                r#"{"id": 1, "secret": "a", "#,
+                r#""padding": "aaaaaaaa"#,
+                "aaaa\"}\n{\"secret\": \"b\"}\n",
+                r#"{"id": 2, "secret": "b", "padding": "aaaaaaaaaaaa"}"#,
+                "\n",
+            ],
+        );
+
+        assert_eq!(
+            vec![
+                "",
+                r#"{"id": 1, "secret": "a", "padding": "aaaaaaaa"#,
+                "aaaa\"}\n{}\n",
+                r#"{"id": 2, "secret": "b", "padding": "aaaaaaaaaaaa"}"#,
+                "\n",
+            ],
+            out
+        );
+        assert!(stream.pending.is_empty());
+    }
+}
@@ -0,0 +1,366 @@
+//! RFC 6902 JSON Patch.
+//!
+//! https://www.rfc-editor.org/rfc/rfc6902
+//!
+//! Patches are applied atomically: if any operation fails (including a `test`
+//! operation), the document is left untouched.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidJsonPointer(String);
+
+impl fmt::Display for InvalidJsonPointer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid JSON pointer => {:?}", self.0)
+    }
+}
+
+/// An RFC 6901 JSON Pointer, e.g. `/data/items/0`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct JsonPointer(String);
+
+impl TryFrom<String> for JsonPointer {
+    type Error = InvalidJsonPointer;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        if !input.is_empty() && !input.starts_with('/') {
+            return Err(InvalidJsonPointer(input));
+        }
+
+        let mut chars = input.chars();
+        while let Some(c) = chars.next() {
+            if c == '~' && !matches!(chars.next(), Some('0' | '1')) {
+                return Err(InvalidJsonPointer(input));
+            }
+        }
+
+        Ok(JsonPointer(input))
+    }
+}
+
+impl fmt::Display for JsonPointer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl JsonPointer {
+    /// Splits the pointer into the pointer of its parent and its (unescaped)
+    /// last reference token. The root pointer has no parent.
+    fn split(&self) -> Option<(&str, String)> {
+        let (parent, token) = self.0.rsplit_once('/')?;
+        Some((parent, token.replace("~1", "/").replace("~0", "~")))
+    }
+
+    fn is_proper_prefix_of(&self, other: &JsonPointer) -> bool {
+        other
+            .0
+            .strip_prefix(&self.0)
+            .is_some_and(|rest| rest.starts_with('/'))
+    }
+}
+
+/// Parses an array index reference token, which may not contain leading
+/// zeros.
+fn parse_index(token: &str) -> Option<usize> {
+    if token.is_empty()
+        || (token.len() > 1 && token.starts_with('0'))
+        || !token.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    token.parse().ok()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PatchError {
+    NotFound(JsonPointer),
+    TestFailed(JsonPointer),
+    MoveIntoChild(JsonPointer),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::NotFound(p) => write!(f, "path {} does not exist", p),
+            PatchError::TestFailed(p) => write!(f, "test of path {} failed", p),
+            PatchError::MoveIntoChild(p) => {
+                write!(f, "cannot move path {} into one of its children", p)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub(crate) enum PatchOperation {
+    Add {
+        path: JsonPointer,
+        value: JsonValue,
+    },
+    Remove {
+        path: JsonPointer,
+    },
+    Replace {
+        path: JsonPointer,
+        value: JsonValue,
+    },
+    Move {
+        from: JsonPointer,
+        path: JsonPointer,
+    },
+    Copy {
+        from: JsonPointer,
+        path: JsonPointer,
+    },
+    Test {
+        path: JsonPointer,
+        value: JsonValue,
+    },
+}
+
+fn add(doc: &mut JsonValue, path: &JsonPointer, value: JsonValue) -> Result<(), PatchError> {
+    let Some((parent, token)) = path.split() else {
+        *doc = value;
+        return Ok(());
+    };
+
+    match doc.pointer_mut(parent) {
+        Some(JsonValue::Object(map)) => {
+            map.insert(token, value);
+            Ok(())
+        }
+        Some(JsonValue::Array(arr)) => {
+            if token == "-" {
+                arr.push(value);
+                return Ok(());
+            }
+
+            match parse_index(&token) {
+                Some(i) if i <= arr.len() => {
+                    arr.insert(i, value);
+                    Ok(())
+                }
+                _ => Err(PatchError::NotFound(path.clone())),
+            }
+        }
+        _ => Err(PatchError::NotFound(path.clone())),
+    }
+}
+
+fn remove(doc: &mut JsonValue, path: &JsonPointer) -> Result<JsonValue, PatchError> {
+    let removed = path
+        .split()
+        .and_then(|(parent, token)| match doc.pointer_mut(parent)? {
+            JsonValue::Object(map) => map.shift_remove(&token),
+            JsonValue::Array(arr) => parse_index(&token)
+                .filter(|i| *i < arr.len())
+                .map(|i| arr.remove(i)),
+            _ => None,
+        });
+
+    removed.ok_or_else(|| PatchError::NotFound(path.clone()))
+}
+
+impl PatchOperation {
+    fn apply(&self, doc: &mut JsonValue) -> Result<(), PatchError> {
+        match self {
+            PatchOperation::Add { path, value } => add(doc, path, value.clone()),
+            PatchOperation::Remove { path } => remove(doc, path).map(|_| ()),
+            PatchOperation::Replace { path, value } => {
+                let target = doc
+                    .pointer_mut(&path.0)
+                    .ok_or_else(|| PatchError::NotFound(path.clone()))?;
+                *target = value.clone();
+                Ok(())
+            }
+            PatchOperation::Move { from, path } => {
+                if from.is_proper_prefix_of(path) {
+                    return Err(PatchError::MoveIntoChild(from.clone()));
+                }
+
+                let value = remove(doc, from)?;
+                add(doc, path, value)
+            }
+            PatchOperation::Copy { from, path } => {
+                let value = doc
+                    .pointer(&from.0)
+                    .cloned()
+                    .ok_or_else(|| PatchError::NotFound(from.clone()))?;
+                add(doc, path, value)
+            }
+            PatchOperation::Test { path, value } => match doc.pointer(&path.0) {
+                Some(found) if found == value => Ok(()),
+                _ => Err(PatchError::TestFailed(path.clone())),
+            },
+        }
+    }
+}
+
+/// Applies a patch to `doc`, returning whether the document was changed.
+pub(crate) fn apply_patch(
+    patch: &[PatchOperation],
+    doc: &mut JsonValue,
+) -> Result<bool, PatchError> {
+    let mut patched = doc.clone();
+
+    for op in patch {
+        op.apply(&mut patched)?;
+    }
+
+    if patched == *doc {
+        return Ok(false);
+    }
+
+    *doc = patched;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patch(value: JsonValue) -> Vec<PatchOperation> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_pointer_try_from() {
+        assert!(JsonPointer::try_from("".to_string()).is_ok());
+        assert!(JsonPointer::try_from("/a/b~0c~1d".to_string()).is_ok());
+        assert!(JsonPointer::try_from("a/b".to_string()).is_err());
+        assert!(JsonPointer::try_from("/a~2".to_string()).is_err());
+        assert!(JsonPointer::try_from("/a~".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_pointer_split() {
+        let p = JsonPointer::try_from("/a/b~1c~01".to_string()).unwrap();
+        assert_eq!(Some(("/a", "b/c~1".to_string())), p.split());
+        assert_eq!(None, JsonPointer(String::new()).split());
+    }
+
+    #[test]
+    fn test_parse_index() {
+        assert_eq!(Some(0), parse_index("0"));
+        assert_eq!(Some(10), parse_index("10"));
+        assert_eq!(None, parse_index("01"));
+        assert_eq!(None, parse_index("-"));
+        assert_eq!(None, parse_index("+1"));
+        assert_eq!(None, parse_index(""));
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        for value in [
+            json!([{ "op": "add", "path": "/a" }]),
+            json!([{ "op": "nope", "path": "/a" }]),
+            json!([{ "op": "remove", "path": "a" }]),
+            json!([{ "op": "move", "path": "/a" }]),
+        ] {
+            assert!(serde_json::from_value::<Vec<PatchOperation>>(value).is_err());
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let ops = patch(json!([
+            { "op": "test", "path": "/version", "value": 1 },
+            { "op": "add", "path": "/items/-", "value": "c" },
+            { "op": "add", "path": "/items/0", "value": "z" },
+            { "op": "remove", "path": "/secret" },
+            { "op": "replace", "path": "/version", "value": 2 },
+            { "op": "move", "from": "/old", "path": "/meta/new" },
+            { "op": "copy", "from": "/meta/new", "path": "/copied" },
+            { "op": "add", "path": "/a~1b", "value": true }
+        ]));
+
+        let mut doc = json!({
+            "version": 1,
+            "items": [ "a", "b" ],
+            "secret": "s",
+            "old": "value",
+            "meta": {}
+        });
+
+        assert_eq!(Ok(true), apply_patch(&ops, &mut doc));
+        assert_eq!(
+            doc,
+            json!({
+                "version": 2,
+                "items": [ "z", "a", "b", "c" ],
+                "meta": { "new": "value" },
+                "copied": "value",
+                "a/b": true
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply_is_atomic() {
+        let ops = patch(json!([
+            { "op": "remove", "path": "/a" },
+            { "op": "test", "path": "/b", "value": "nope" }
+        ]));
+
+        let mut doc = json!({ "a": 1, "b": 2 });
+        let expect = doc.clone();
+
+        assert_eq!(
+            Err(PatchError::TestFailed(JsonPointer("/b".to_string()))),
+            apply_patch(&ops, &mut doc)
+        );
+        assert_eq!(doc, expect);
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let mut doc = json!({ "a": { "b": 1 }, "arr": [ 1 ] });
+
+        for ops in [
+            json!([{ "op": "remove", "path": "/missing" }]),
+            json!([{ "op": "replace", "path": "/missing", "value": 1 }]),
+            json!([{ "op": "add", "path": "/missing/child", "value": 1 }]),
+            json!([{ "op": "add", "path": "/arr/5", "value": 1 }]),
+            json!([{ "op": "remove", "path": "/arr/01" }]),
+            json!([{ "op": "move", "from": "/a", "path": "/a/c" }]),
+            json!([{ "op": "copy", "from": "/missing", "path": "/c" }]),
+        ] {
+            assert!(
+                apply_patch(&patch(ops.clone()), &mut doc).is_err(),
+                "{}",
+                ops
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_unchanged() {
+        let ops = patch(json!([
+            { "op": "test", "path": "/a", "value": 1 },
+            { "op": "replace", "path": "/a", "value": 1 }
+        ]));
+
+        let mut doc = json!({ "a": 1 });
+        assert_eq!(Ok(false), apply_patch(&ops, &mut doc));
+    }
+
+    #[test]
+    fn test_apply_root() {
+        let ops = patch(json!([{ "op": "add", "path": "", "value": { "new": true } }]));
+
+        let mut doc = json!({ "a": 1 });
+        assert_eq!(Ok(true), apply_patch(&ops, &mut doc));
+        assert_eq!(doc, json!({ "new": true }));
+    }
+}
@@ -0,0 +1,698 @@
+//! JSON field paths.
+//!
+//! Paths use dot notation to descend into objects and brackets to index into
+//! arrays, e.g. `data.items[0].id`. A literal `.`, `[`, `]` or `\` within a key
+//! must be escaped with a backslash, so `a\.b` refers to the key `a.b` rather
+//! than the key `b` within the object `a`.
+//!
+//! Paths may also fan out to multiple values:
+//!
+//! * `[*]` selects every element of an array (`items[*].internal_id`)
+//! * `*` selects every member of an object (`users.*.password`)
+//! * `**` selects a value and all of its descendants (`**.secret`)
+//!
+//! A key consisting solely of `*` or `**` can be escaped (`\*`) to match it
+//! literally.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::json::JsonMap;
+use serde_json::Value as JsonValue;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment {
+    Key(String),
+    Index(usize),
+    AnyKey,
+    AnyIndex,
+    Descendants,
+}
+
+impl Segment {
+    fn is_wildcard(&self) -> bool {
+        matches!(
+            self,
+            Segment::AnyKey | Segment::AnyIndex | Segment::Descendants
+        )
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Segment::Key(key) => {
+                let wildcard = key == "*" || key == "**";
+
+                for c in key.chars() {
+                    if matches!(c, '.' | '[' | ']' | '\\') || (wildcard && c == '*') {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+                Ok(())
+            }
+            Segment::Index(i) => write!(f, "[{}]", i),
+            Segment::AnyKey => write!(f, "*"),
+            Segment::AnyIndex => write!(f, "[*]"),
+            Segment::Descendants => write!(f, "**"),
+        }
+    }
+}
+
+/// Calls `f` with every value selected by `segments`.
+///
+/// When `create` is set, missing object members are created along the way,
+/// provided that only object keys follow them, so that the caller (which is
+/// expected to insert an object key) always reaches the new object. Nothing is
+/// created for a path that cannot be completed.
+fn walk(
+    value: &mut JsonValue,
+    segments: &[Segment],
+    create: bool,
+    f: &mut dyn FnMut(&mut JsonValue),
+) {
+    let Some((segment, rest)) = segments.split_first() else {
+        f(value);
+        return;
+    };
+
+    match (segment, value) {
+        (Segment::Key(key), JsonValue::Object(map)) => {
+            let only_keys = rest.iter().all(|s| matches!(s, Segment::Key(_)));
+
+            let child = if create && only_keys {
+                Some(
+                    map.entry(key)
+                        .or_insert_with(|| JsonValue::Object(JsonMap::new())),
+                )
+            } else {
+                map.get_mut(key)
+            };
+
+            if let Some(child) = child {
+                walk(child, rest, create, f);
+            }
+        }
+        (Segment::Index(i), JsonValue::Array(arr)) => {
+            if let Some(child) = arr.get_mut(*i) {
+                walk(child, rest, create, f);
+            }
+        }
+        (Segment::AnyKey, JsonValue::Object(map)) => {
+            map.values_mut()
+                .for_each(|child| walk(child, rest, create, f));
+        }
+        (Segment::AnyIndex, JsonValue::Array(arr)) => {
+            arr.iter_mut()
+                .for_each(|child| walk(child, rest, create, f));
+        }
+        (Segment::Descendants, value) => {
+            // visit children first so that anything the caller adds to this
+            // value is not visited again
+            match &mut *value {
+                JsonValue::Object(map) => map
+                    .values_mut()
+                    .for_each(|child| walk(child, segments, create, f)),
+                JsonValue::Array(arr) => arr
+                    .iter_mut()
+                    .for_each(|child| walk(child, segments, create, f)),
+                _ => {}
+            }
+
+            walk(value, rest, create, f);
+        }
+        _ => {}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidJsonPath {
+    path: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for InvalidJsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid JSON path {:?}: {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidJsonPath {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JsonPath(Vec<Segment>);
+
+impl TryFrom<&str> for JsonPath {
+    type Error = InvalidJsonPath;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let err = |reason| InvalidJsonPath {
+            path: input.to_owned(),
+            reason,
+        };
+
+        fn key_segment(key: String, escaped: bool) -> Segment {
+            match key.as_str() {
+                "*" if !escaped => Segment::AnyKey,
+                "**" if !escaped => Segment::Descendants,
+                _ => Segment::Key(key),
+            }
+        }
+
+        let mut segments = Vec::new();
+        let mut key = String::new();
+        let mut in_key = false;
+        let mut escaped = false;
+        let mut after_dot = false;
+        let mut chars = input.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    if in_key {
+                        segments.push(key_segment(std::mem::take(&mut key), escaped));
+                        in_key = false;
+                    } else if segments.is_empty() || after_dot {
+                        return Err(err("empty key"));
+                    }
+                    after_dot = true;
+                }
+                '[' => {
+                    if in_key {
+                        segments.push(key_segment(std::mem::take(&mut key), escaped));
+                        in_key = false;
+                    } else if after_dot {
+                        return Err(err("empty key"));
+                    }
+
+                    let mut index = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => index.push(c),
+                            None => return Err(err("unterminated array index")),
+                        }
+                    }
+
+                    if index == "*" {
+                        segments.push(Segment::AnyIndex);
+                    } else if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) {
+                        let index = index.parse().map_err(|_| err("invalid array index"))?;
+                        segments.push(Segment::Index(index));
+                    } else {
+                        return Err(err("invalid array index"));
+                    }
+
+                    after_dot = false;
+                }
+                ']' => return Err(err("unexpected ']'")),
+                c => {
+                    if !in_key && !after_dot && !segments.is_empty() {
+                        return Err(err("expected '.' or '[' after array index"));
+                    }
+
+                    if !in_key {
+                        escaped = false;
+                    }
+
+                    let c = if c == '\\' {
+                        escaped = true;
+                        chars
+                            .next()
+                            .ok_or_else(|| err("trailing escape character"))?
+                    } else {
+                        c
+                    };
+
+                    key.push(c);
+                    in_key = true;
+                    after_dot = false;
+                }
+            }
+        }
+
+        if in_key {
+            segments.push(key_segment(key, escaped));
+        } else if after_dot {
+            return Err(err("empty key"));
+        }
+
+        match segments.last() {
+            None => Err(err("empty path")),
+            Some(Segment::Descendants) => Err(err("path cannot end with '**'")),
+            Some(_) => Ok(JsonPath(segments)),
+        }
+    }
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = InvalidJsonPath;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        JsonPath::try_from(input.as_str())
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            if i > 0 && !matches!(segment, Segment::Index(_) | Segment::AnyIndex) {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+impl JsonPath {
+    /// Parses a path that refers to an object member rather than an array
+    /// element.
+    pub(crate) fn member(input: &str) -> Result<Self, InvalidJsonPath> {
+        let path = JsonPath::try_from(input)?;
+
+        match path.split_last() {
+            (_, Segment::Key(_)) => Ok(path),
+            _ => Err(InvalidJsonPath {
+                path: input.to_owned(),
+                reason: "path must end with an object key",
+            }),
+        }
+    }
+
+    /// Parses the source and destination paths of a rename.
+    ///
+    /// A value matched by wildcards can only be renamed in place, so in that
+    /// case both paths must share the same parent.
+    pub(crate) fn rename_pair(from: &str, to: &str) -> Result<(Self, Self), InvalidJsonPath> {
+        let from_path = JsonPath::member(from)?;
+        let to_path = JsonPath::member(to)?;
+
+        let wildcards = from_path.has_wildcards() || to_path.has_wildcards();
+        if wildcards && from_path.split_last().0 != to_path.split_last().0 {
+            return Err(InvalidJsonPath {
+                path: to.to_owned(),
+                reason: "wildcard renames must keep the same parent path",
+            });
+        }
+
+        Ok((from_path, to_path))
+    }
+
+    /// Returns the key if this path refers to a single top-level object
+    /// member.
+    pub(crate) fn as_key(&self) -> Option<&str> {
+        match self.0.as_slice() {
+            [Segment::Key(key)] => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Returns the last object key in this path, if any. Nothing can match
+    /// this path unless a document has a member with this name.
+    pub(crate) fn last_key(&self) -> Option<&str> {
+        self.0.iter().rev().find_map(|segment| match segment {
+            Segment::Key(key) => Some(key.as_str()),
+            _ => None,
+        })
+    }
+
+    fn split_last(&self) -> (&[Segment], &Segment) {
+        let (last, parent) = self.0.split_last().expect("JSON paths are never empty");
+        (parent, last)
+    }
+
+    fn has_wildcards(&self) -> bool {
+        self.0.iter().any(Segment::is_wildcard)
+    }
+
+    /// Calls `f` with every value matched by this path.
+    pub(crate) fn for_each_mut(&self, root: &mut JsonValue, mut f: impl FnMut(&mut JsonValue)) {
+        walk(root, &self.0, false, &mut f);
+    }
+
+    /// Calls `f` with the parent object and key of every member matched by
+    /// this path, whether or not the member itself exists.
+    ///
+    /// When `create` is set, missing intermediate objects are created. Array
+    /// elements are never created, and non-container values are never
+    /// overwritten.
+    pub(crate) fn for_each_member(
+        &self,
+        root: &mut JsonValue,
+        create: bool,
+        mut f: impl FnMut(&mut JsonMap, &str),
+    ) {
+        let (parent, Segment::Key(key)) = self.split_last() else {
+            return;
+        };
+
+        walk(root, parent, create, &mut |value| {
+            if let JsonValue::Object(map) = value {
+                f(map, key);
+            }
+        });
+    }
+
+    /// Removes every value matched by this path, returning them. The order of
+    /// the remaining object members is preserved.
+    pub(crate) fn remove(&self, root: &mut JsonValue) -> Vec<JsonValue> {
+        let (parent, last) = self.split_last();
+        let mut removed = vec![];
+
+        walk(root, parent, false, &mut |value| match (last, value) {
+            (Segment::Key(key), JsonValue::Object(map)) => removed.extend(map.shift_remove(key)),
+            (Segment::Index(i), JsonValue::Array(arr)) if *i < arr.len() => {
+                removed.push(arr.remove(*i))
+            }
+            (Segment::AnyKey, JsonValue::Object(map)) => {
+                removed.extend(std::mem::take(map).into_iter().map(|(_, v)| v))
+            }
+            (Segment::AnyIndex, JsonValue::Array(arr)) => removed.append(arr),
+            _ => {}
+        });
+
+        removed
+    }
+
+    /// Moves every value matched by this path to `to`, returning the number
+    /// of values that were moved.
+    ///
+    /// Paths are expected to have been validated by `rename_pair()`.
+    pub(crate) fn rename(&self, root: &mut JsonValue, to: &JsonPath) -> usize {
+        let mut renamed = 0;
+
+        if let (parent, Segment::Key(to_key)) = to.split_last() {
+            if parent == self.split_last().0 {
+                self.for_each_member(root, false, |map, key| {
+                    if rename_key(map, key, to_key) {
+                        renamed += 1;
+                    }
+                });
+
+                return renamed;
+            }
+        }
+
+        // the positions of the matched members, in case they have to be put
+        // back
+        let mut positions = vec![];
+        self.for_each_member(root, false, |map, key| {
+            positions.extend(map.keys().position(|k| k == key));
+        });
+
+        for (value, position) in self.remove(root).into_iter().zip(positions) {
+            let mut value = Some(value);
+
+            to.for_each_member(root, true, |map, key| {
+                if let Some(v) = value.take() {
+                    map.insert(key.to_owned(), v);
+                    renamed += 1;
+                }
+            });
+
+            // the destination could not be reached, so put the value back
+            // where it came from
+            if let Some(v) = value {
+                self.for_each_member(root, false, |map, key| {
+                    insert_at(map, position, key, v.clone());
+                });
+            }
+        }
+
+        renamed
+    }
+}
+
+/// Renames an object member, keeping its position. An existing member with
+/// the new name is replaced.
+fn rename_key(map: &mut JsonMap, from: &str, to: &str) -> bool {
+    if !map.contains_key(from) {
+        return false;
+    }
+
+    if from != to {
+        map.shift_remove(to);
+
+        *map = std::mem::take(map)
+            .into_iter()
+            .map(|(key, value)| {
+                if key == from {
+                    (to.to_owned(), value)
+                } else {
+                    (key, value)
+                }
+            })
+            .collect();
+    }
+
+    true
+}
+
+/// Inserts an object member at `index`, shifting the members after it.
+fn insert_at(map: &mut JsonMap, index: usize, key: &str, value: JsonValue) {
+    let mut members: Vec<_> = std::mem::take(map).into_iter().collect();
+    members.insert(index.min(members.len()), (key.to_owned(), value));
+    *map = members.into_iter().collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn path(s: &str) -> JsonPath {
+        JsonPath::try_from(s).unwrap()
+    }
+
+    fn key(s: &str) -> Segment {
+        Segment::Key(s.to_string())
+    }
+
+    fn matches(p: &str, mut value: JsonValue) -> Vec<JsonValue> {
+        let mut found = vec![];
+        path(p).for_each_mut(&mut value, |v| found.push(v.clone()));
+        found
+    }
+
+    #[test]
+    fn test_parse_valid() {
+        assert_eq!(JsonPath(vec![key("a")]), path("a"));
+        assert_eq!(JsonPath(vec![key("a"), key("b")]), path("a.b"));
+        assert_eq!(JsonPath(vec![key("a.b")]), path(r"a\.b"));
+        assert_eq!(JsonPath(vec![key(r"a\b")]), path(r"a\\b"));
+        assert_eq!(JsonPath(vec![key("a[0]")]), path(r"a\[0\]"));
+        assert_eq!(
+            JsonPath(vec![key("items"), Segment::Index(0), key("id")]),
+            path("items[0].id")
+        );
+        assert_eq!(
+            JsonPath(vec![key("a"), Segment::Index(1), Segment::Index(2)]),
+            path("a[1][2]")
+        );
+        assert_eq!(JsonPath(vec![Segment::Index(3), key("a")]), path("[3].a"));
+    }
+
+    #[test]
+    fn test_parse_wildcards() {
+        assert_eq!(
+            JsonPath(vec![key("items"), Segment::AnyIndex, key("id")]),
+            path("items[*].id")
+        );
+        assert_eq!(
+            JsonPath(vec![key("users"), Segment::AnyKey, key("password")]),
+            path("users.*.password")
+        );
+        assert_eq!(
+            JsonPath(vec![Segment::Descendants, key("secret")]),
+            path("**.secret")
+        );
+        assert_eq!(JsonPath(vec![key("*")]), path(r"\*"));
+        assert_eq!(JsonPath(vec![key("**")]), path(r"*\*"));
+        assert_eq!(JsonPath(vec![key("a*")]), path("a*"));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for input in [
+            "", ".", "a.", ".a", "a..b", "a.[0]", "a[", "a[x]", "a[-1]", "a[]", "a]", "a[0]b",
+            r"a\", "**", "a.**", "a[**]",
+        ] {
+            assert!(JsonPath::try_from(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_member() {
+        assert!(JsonPath::member("a[0].b").is_ok());
+        assert!(JsonPath::member("a[*].b").is_ok());
+        assert!(JsonPath::member("a.b[0]").is_err());
+        assert!(JsonPath::member("a.b[*]").is_err());
+        assert!(JsonPath::member("a.*").is_err());
+    }
+
+    #[test]
+    fn test_parse_rename_pair() {
+        assert!(JsonPath::rename_pair("a.b", "c.d").is_ok());
+        assert!(JsonPath::rename_pair("a[*].b", "a[*].c").is_ok());
+        assert!(JsonPath::rename_pair("**.b", "**.c").is_ok());
+        assert!(JsonPath::rename_pair("a[*].b", "c").is_err());
+        assert!(JsonPath::rename_pair("a.b", "a[*].c").is_err());
+    }
+
+    #[test]
+    fn test_display_roundtrip() {
+        for input in [
+            "a",
+            "a.b",
+            r"a\.b",
+            r"a\\b",
+            "items[0].id",
+            "a[1][2]",
+            "[3].a",
+            "items[*].id",
+            "[*][*]",
+            "users.*.password",
+            "**.secret",
+            r"\*.\*\*",
+        ] {
+            assert_eq!(input, path(input).to_string());
+        }
+    }
+
+    #[test]
+    fn test_for_each_mut() {
+        let value = json!({ "a": { "b": [ { "c": 1 }, { "c": 2 } ] }, "a.b": 3 });
+
+        assert_eq!(vec![json!(1)], matches("a.b[0].c", value.clone()));
+        assert_eq!(vec![json!(3)], matches(r"a\.b", value.clone()));
+        assert_eq!(vec![json!(1), json!(2)], matches("a.b[*].c", value.clone()));
+        assert_eq!(vec![json!(1), json!(2)], matches("**.c", value.clone()));
+        assert!(matches("a.b[2].c", value.clone()).is_empty());
+        assert!(matches("a.b.c", value).is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut value = json!({ "a": { "b": [ 1, 2, 3 ], "c": true } });
+
+        assert_eq!(vec![json!(2)], path("a.b[1]").remove(&mut value));
+        assert_eq!(vec![json!(true)], path("a.c").remove(&mut value));
+        assert!(path("a.c").remove(&mut value).is_empty());
+        assert!(path("a.b[5]").remove(&mut value).is_empty());
+        assert_eq!(json!({ "a": { "b": [ 1, 3 ] } }), value);
+    }
+
+    #[test]
+    fn test_remove_wildcards() {
+        let mut value = json!({
+            "items": [
+                { "id": 1, "internal_id": "x" },
+                { "id": 2, "internal_id": "y" }
+            ],
+            "secret": 1,
+            "nested": { "deeper": [ { "secret": 2 } ], "other": 3 }
+        });
+
+        assert_eq!(2, path("items[*].internal_id").remove(&mut value).len());
+        assert_eq!(2, path("**.secret").remove(&mut value).len());
+        assert_eq!(
+            json!({
+                "items": [ { "id": 1 }, { "id": 2 } ],
+                "nested": { "deeper": [ {} ], "other": 3 }
+            }),
+            value
+        );
+
+        assert_eq!(2, path("nested.*").remove(&mut value).len());
+        assert_eq!(2, path("items[*]").remove(&mut value).len());
+        assert_eq!(json!({ "items": [], "nested": {} }), value);
+    }
+
+    #[test]
+    fn test_for_each_member_create() {
+        let mut value = json!({ "a": { "b": [ {} ] }, "s": "string" });
+
+        let mut add = |p: &str, v: JsonValue| {
+            let mut added = false;
+            path(p).for_each_member(&mut value, true, |map, key| {
+                map.insert(key.to_owned(), v.clone());
+                added = true;
+            });
+            added
+        };
+
+        assert!(add("x.y.z", json!(1)));
+        assert!(add("a.b[0].c", json!(2)));
+
+        // no intermediate arrays or array elements are created, nor any
+        // object leading to them
+        assert!(!add("a.b[1].c", json!(3)));
+        assert!(!add("n[0].c", json!(4)));
+        assert!(!add("x.n[0].c", json!(4)));
+        assert!(!add("m.*.c", json!(4)));
+
+        // non-container values are not overwritten
+        assert!(!add("s.t", json!(5)));
+
+        assert_eq!(
+            json!({
+                "a": { "b": [ { "c": 2 } ] },
+                "s": "string",
+                "x": { "y": { "z": 1 } }
+            }),
+            value
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut value = json!({
+            "a": { "b": 1 },
+            "items": [ { "old": 1 }, { "old": 2 }, {} ],
+            "s": "string"
+        });
+
+        assert_eq!(1, path("a.b").rename(&mut value, &path("c.d")));
+        assert_eq!(
+            2,
+            path("items[*].old").rename(&mut value, &path("items[*].new"))
+        );
+
+        // destination is unreachable, value is left in place
+        assert_eq!(0, path("c.d").rename(&mut value, &path("s.d")));
+
+        assert_eq!(
+            json!({
+                "a": {},
+                "c": { "d": 1 },
+                "items": [ { "new": 1 }, { "new": 2 }, {} ],
+                "s": "string"
+            }),
+            value
+        );
+    }
+
+    #[test]
+    fn test_member_order() {
+        let mut value: JsonValue =
+            serde_json::from_str(r#"{"z":1,"old":2,"a":3,"new":4,"m":5}"#).unwrap();
+
+        path("a").remove(&mut value);
+        assert_eq!(1, path("old").rename(&mut value, &path("new")));
+        assert_eq!(1, path("m").rename(&mut value, &path("m")));
+
+        assert_eq!(
+            r#"{"z":1,"new":2,"m":5}"#,
+            serde_json::to_string(&value).unwrap()
+        );
+
+        // a value that cannot be moved is put back where it was
+        assert_eq!(0, path("new").rename(&mut value, &path("z.x")));
+        assert_eq!(
+            r#"{"z":1,"new":2,"m":5}"#,
+            serde_json::to_string(&value).unwrap()
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Request data captured in `on_http_request_headers`.
+//!
+//! Response callbacks cannot read request headers, so anything that rule
+//! conditions or templates need from the request is copied into the HTTP
+//! context before the request is forwarded upstream.
+
+use std::collections::HashMap;
+
+use crate::form;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct RequestInfo {
+    pub(crate) method: Option<String>,
+    /// The request path, without the query string.
+    pub(crate) path: Option<String>,
+    /// The raw query string, without the leading `?`.
+    pub(crate) query: Option<String>,
+    pub(crate) authority: Option<String>,
+    /// Captured request headers, keyed by lowercase name.
+    pub(crate) headers: HashMap<String, String>,
+}
+
+impl RequestInfo {
+    /// Sets the path and query string from a `:path` pseudo-header.
+    pub(crate) fn set_path(&mut self, path: &str) {
+        match path.split_once('?') {
+            Some((path, query)) => {
+                self.path = Some(path.to_owned());
+                self.query = Some(query.to_owned());
+            }
+            None => {
+                self.path = Some(path.to_owned());
+                self.query = None;
+            }
+        }
+    }
+
+    /// Returns the lowercase host name from the authority, without the port.
+    pub(crate) fn host(&self) -> Option<String> {
+        self.authority
+            .as_deref()
+            .map(|authority| strip_port(authority).to_lowercase())
+    }
+
+    /// Returns the (decoded) value of the first query string argument with
+    /// the given name. Arguments without a value have an empty value.
+    pub(crate) fn query_arg(&self, name: &str) -> Option<String> {
+        self.query
+            .as_deref()?
+            .split('&')
+            .map(|arg| arg.split_once('=').unwrap_or((arg, "")))
+            .find(|(key, _)| form::decode(key) == name)
+            .map(|(_, value)| form::decode(value))
+    }
+}
+
+/// Strips the port from a request authority, taking care not to break
+/// bracketed IPv6 addresses like `[::1]:8080`.
+fn strip_port(authority: &str) -> &str {
+    match authority.rsplit_once(':') {
+        Some((host, port))
+            if port.bytes().all(|b| b.is_ascii_digit())
+                && (!host.contains(':') || host.ends_with(']')) =>
+        {
+            host
+        }
+        _ => authority,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_path() {
+        let mut request = RequestInfo::default();
+
+        request.set_path("/a/b?c=d&e");
+        assert_eq!(Some("/a/b"), request.path.as_deref());
+        assert_eq!(Some("c=d&e"), request.query.as_deref());
+
+        request.set_path("/a");
+        assert_eq!(Some("/a"), request.path.as_deref());
+        assert_eq!(None, request.query);
+
+        request.set_path("/?");
+        assert_eq!(Some("/"), request.path.as_deref());
+        assert_eq!(Some(""), request.query.as_deref());
+    }
+
+    #[test]
+    fn test_host() {
+        let host = |authority: &str| {
+            RequestInfo {
+                authority: Some(authority.to_owned()),
+                ..Default::default()
+            }
+            .host()
+            .unwrap()
+        };
+
+        assert_eq!("example.com", host("Example.COM:8080"));
+        assert_eq!("example.com", host("example.com"));
+        assert_eq!("[::1]", host("[::1]:8080"));
+        assert_eq!("[::1]", host("[::1]"));
+        assert_eq!("example.com:http", host("example.com:http"));
+        assert_eq!(None, RequestInfo::default().host());
+    }
+
+    #[test]
+    fn test_query_arg() {
+        let request = RequestInfo {
+            query: Some("a=1&b&c=x+y%2Fz&a=2&d%20e=%zz%4".to_owned()),
+            ..Default::default()
+        };
+
+        assert_eq!(Some("1".to_owned()), request.query_arg("a"));
+        assert_eq!(Some("".to_owned()), request.query_arg("b"));
+        assert_eq!(Some("x y/z".to_owned()), request.query_arg("c"));
+        assert_eq!(Some("%zz%4".to_owned()), request.query_arg("d e"));
+        assert_eq!(None, request.query_arg("missing"));
+        assert_eq!(None, RequestInfo::default().query_arg("a"));
+    }
+}
@@ -0,0 +1,101 @@
+//! Fast scans over raw JSON documents.
+//!
+//! Parsing a body is wasted work when the transformations cannot change it,
+//! e.g. when none of the members they refer to are present. The scans here
+//! look at the raw bytes instead, without allocating (except to decode member
+//! names that contain escape sequences).
+
+fn is_ws(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Returns whether any object in a JSON document has a member named one of
+/// `keys`. The document is not validated, so this may return false for
+/// invalid documents.
+pub(crate) fn has_member(body: &[u8], keys: &[&str]) -> bool {
+    let mut i = 0;
+
+    while i < body.len() {
+        if body[i] != b'"' {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut escaped = false;
+        i += 1;
+
+        while i < body.len() && body[i] != b'"' {
+            if body[i] == b'\\' {
+                escaped = true;
+                i += 1;
+            }
+            i += 1;
+        }
+
+        if i >= body.len() {
+            return false;
+        }
+
+        i += 1;
+        let string = &body[start..i];
+
+        // only strings followed by a colon are member names
+        let is_name = body[i..]
+            .iter()
+            .find(|b| !is_ws(**b))
+            .is_some_and(|b| *b == b':');
+
+        if !is_name {
+            continue;
+        }
+
+        let found = if escaped {
+            serde_json::from_slice::<String>(string)
+                .is_ok_and(|name| keys.iter().any(|key| *key == name))
+        } else {
+            let name = &string[1..string.len() - 1];
+            keys.iter().any(|key| key.as_bytes() == name)
+        };
+
+        if found {
+            return true;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_member() {
+        let body = br#"{"id": 1, "user": {"name" : "a", "note": "secret"}, "tags": ["token"]}"#;
+
+        assert!(has_member(body, &["id"]));
+        assert!(has_member(body, &["missing", "name"]));
+        assert!(has_member(body, &["note"]));
+        assert!(!has_member(body, &["secret"]));
+        assert!(!has_member(body, &["token"]));
+        assert!(!has_member(body, &["i"]));
+        assert!(!has_member(body, &[]));
+    }
+
+    #[test]
+    fn test_has_member_escapes() {
+        assert!(has_member(br#"{"a\u0062c": 1}"#, &["abc"]));
+        assert!(has_member(br#"{"a\"b": 1}"#, &["a\"b"]));
+        assert!(has_member(br#"{"x": "\"", "y\\": 2}"#, &["y\\"]));
+        assert!(!has_member(br#"{"x": "\"y\":"}"#, &["y"]));
+        assert!(!has_member(br#"{"x": "\"y\":"}"#, &["y\\"]));
+    }
+
+    #[test]
+    fn test_has_member_invalid() {
+        assert!(!has_member(br#"{"a"#, &["a"]));
+        assert!(!has_member(b"", &["a"]));
+        assert!(has_member(br#"{"a": "#, &["a"]));
+    }
+}
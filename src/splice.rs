@@ -0,0 +1,528 @@
+//! Byte-level edits of JSON documents.
+//!
+//! Rather than re-serializing a transformed document from scratch, the
+//! original bytes are parsed into a tree of spans, which is then walked
+//! alongside the transformed value. Members, elements and scalars that are
+//! unchanged are copied from the original, so whitespace, escape sequences and
+//! number formatting outside of the edited parts stay byte-identical. Only new
+//! or changed values are serialized (compactly).
+//!
+//! Object members are matched up with the original ones by name, and array
+//! elements in order, skipping the original elements that were removed.
+
+use std::borrow::Cow;
+
+use serde_json::Value as JsonValue;
+
+fn is_ws(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+#[derive(Debug)]
+enum Node<'a> {
+    /// A string, number, boolean or null, as it appears in the document.
+    Scalar(&'a [u8]),
+    Object {
+        members: Vec<Member<'a>>,
+        /// Whitespace before the closing brace.
+        close: &'a [u8],
+    },
+    Array {
+        items: Vec<Item<'a>>,
+        /// Whitespace before the closing bracket.
+        close: &'a [u8],
+    },
+}
+
+#[derive(Debug)]
+struct Member<'a> {
+    /// Whitespace before the name.
+    lead: &'a [u8],
+    /// The quoted name, as it appears in the document.
+    key: &'a [u8],
+    name: Cow<'a, str>,
+    /// The colon and the whitespace around it.
+    colon: &'a [u8],
+    value: Node<'a>,
+    /// Whitespace before the following comma. The whitespace after the last
+    /// member is the `close` of the object.
+    trail: &'a [u8],
+}
+
+#[derive(Debug)]
+struct Item<'a> {
+    lead: &'a [u8],
+    value: Node<'a>,
+    trail: &'a [u8],
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn ws(&mut self) -> &'a [u8] {
+        let start = self.pos;
+
+        while self.pos < self.input.len() && is_ws(self.input[self.pos]) {
+            self.pos += 1;
+        }
+
+        &self.input[start..self.pos]
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn value(&mut self) -> Option<Node<'a>> {
+        match self.peek()? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => self.string().map(Node::Scalar),
+            _ => {
+                let start = self.pos;
+
+                while self
+                    .peek()
+                    .is_some_and(|b| !is_ws(b) && !matches!(b, b',' | b'}' | b']'))
+                {
+                    self.pos += 1;
+                }
+
+                let scalar = &self.input[start..self.pos];
+                (!scalar.is_empty()).then_some(Node::Scalar(scalar))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let start = self.pos;
+
+        if self.next()? != b'"' {
+            return None;
+        }
+
+        loop {
+            match self.next()? {
+                b'"' => return Some(&self.input[start..self.pos]),
+                b'\\' => {
+                    self.next()?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn object(&mut self) -> Option<Node<'a>> {
+        self.pos += 1;
+        let mut members = vec![];
+
+        loop {
+            let lead = self.ws();
+
+            if members.is_empty() && self.peek()? == b'}' {
+                self.pos += 1;
+                return Some(Node::Object {
+                    members,
+                    close: lead,
+                });
+            }
+
+            let key = self.string()?;
+
+            let colon_start = self.pos;
+            self.ws();
+            if self.next()? != b':' {
+                return None;
+            }
+            self.ws();
+            let colon = &self.input[colon_start..self.pos];
+
+            let value = self.value()?;
+            let trail = self.ws();
+
+            let mut member = Member {
+                lead,
+                key,
+                name: decode_name(key)?,
+                colon,
+                value,
+                trail,
+            };
+
+            match self.next()? {
+                b',' => members.push(member),
+                b'}' => {
+                    member.trail = b"";
+                    members.push(member);
+                    return Some(Node::Object {
+                        members,
+                        close: trail,
+                    });
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Node<'a>> {
+        self.pos += 1;
+        let mut items = vec![];
+
+        loop {
+            let lead = self.ws();
+
+            if items.is_empty() && self.peek()? == b']' {
+                self.pos += 1;
+                return Some(Node::Array { items, close: lead });
+            }
+
+            let value = self.value()?;
+            let trail = self.ws();
+
+            match self.next()? {
+                b',' => items.push(Item { lead, value, trail }),
+                b']' => {
+                    items.push(Item {
+                        lead,
+                        value,
+                        trail: b"",
+                    });
+                    return Some(Node::Array {
+                        items,
+                        close: trail,
+                    });
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+fn decode_name(key: &[u8]) -> Option<Cow<'_, str>> {
+    if key.contains(&b'\\') {
+        serde_json::from_slice(key).ok().map(Cow::Owned)
+    } else {
+        std::str::from_utf8(&key[1..key.len() - 1])
+            .ok()
+            .map(Cow::Borrowed)
+    }
+}
+
+/// Returns whether a scalar from the document has the given value.
+fn is_same_scalar(raw: &[u8], value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => raw == b"null",
+        JsonValue::Bool(true) => raw == b"true",
+        JsonValue::Bool(false) => raw == b"false",
+        JsonValue::Number(n) => raw == n.as_str().as_bytes(),
+        JsonValue::String(s) if raw.first() == Some(&b'"') => {
+            if raw.contains(&b'\\') {
+                serde_json::from_slice::<String>(raw).is_ok_and(|found| found == *s)
+            } else {
+                &raw[1..raw.len() - 1] == s.as_bytes()
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Returns whether `value` could be `node` with some object members removed.
+fn resembles(node: &Node, value: &JsonValue) -> bool {
+    match (node, value) {
+        (Node::Scalar(raw), value) => is_same_scalar(raw, value),
+        (Node::Object { members, .. }, JsonValue::Object(map)) => {
+            map.iter().all(|(name, value)| {
+                members
+                    .iter()
+                    .any(|member| member.name == *name && resembles(&member.value, value))
+            })
+        }
+        (Node::Array { items, .. }, JsonValue::Array(values)) => {
+            items.len() == values.len()
+                && items
+                    .iter()
+                    .zip(values)
+                    .all(|(item, value)| resembles(&item.value, value))
+        }
+        _ => false,
+    }
+}
+
+/// Writes `value`, copying anything it has in common with `node`.
+fn write(node: &Node, value: &JsonValue, out: &mut Vec<u8>) -> serde_json::Result<()> {
+    match (node, value) {
+        (Node::Object { members, close }, JsonValue::Object(map)) => {
+            out.push(b'{');
+
+            let mut next = 0;
+            let mut trail: &[u8] = b"";
+
+            for (i, (name, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.extend_from_slice(trail);
+                    out.push(b',');
+                }
+
+                // members are usually in their original order
+                let found = match members.get(next) {
+                    Some(member) if member.name == *name => Some(next),
+                    _ => members.iter().position(|member| member.name == *name),
+                };
+
+                match found {
+                    Some(j) => {
+                        let member = &members[j];
+                        out.extend_from_slice(member.lead);
+                        out.extend_from_slice(member.key);
+                        out.extend_from_slice(member.colon);
+                        write(&member.value, value, out)?;
+                        trail = member.trail;
+                        next = j + 1;
+                    }
+                    None => {
+                        // formatted like the last of the original members
+                        let last = members.last();
+                        out.extend_from_slice(last.map_or(b"", |m| m.lead));
+                        serde_json::to_writer(&mut *out, name)?;
+                        out.extend_from_slice(last.map_or(b":", |m| m.colon));
+                        serde_json::to_writer(&mut *out, value)?;
+                        trail = b"";
+                    }
+                }
+            }
+
+            out.extend_from_slice(close);
+            out.push(b'}');
+        }
+        (Node::Array { items, close }, JsonValue::Array(values)) => {
+            out.push(b'[');
+
+            let mut next = 0;
+            let mut removed = items.len().saturating_sub(values.len());
+            let mut trail: &[u8] = b"";
+
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.extend_from_slice(trail);
+                    out.push(b',');
+                }
+
+                // skip the original elements that were removed, if the next
+                // ones match up, or else treat the element as changed in place
+                let skip = (0..=removed).find(|skip| {
+                    items
+                        .get(next + skip)
+                        .is_some_and(|item| resembles(&item.value, value))
+                });
+
+                if let Some(skip) = skip {
+                    next += skip;
+                    removed -= skip;
+                }
+
+                match items.get(next) {
+                    Some(item) => {
+                        out.extend_from_slice(item.lead);
+                        write(&item.value, value, out)?;
+                        trail = item.trail;
+                        next += 1;
+                    }
+                    None => {
+                        out.extend_from_slice(items.last().map_or(b"", |item| item.lead));
+                        serde_json::to_writer(&mut *out, value)?;
+                        trail = b"";
+                    }
+                }
+            }
+
+            out.extend_from_slice(close);
+            out.push(b']');
+        }
+        (Node::Scalar(raw), value) if is_same_scalar(raw, value) => out.extend_from_slice(raw),
+        (_, value) => serde_json::to_writer(&mut *out, value)?,
+    }
+
+    Ok(())
+}
+
+/// Serializes `value`, a transformed copy of the document in `original`,
+/// keeping the original bytes of everything that was not changed.
+pub(crate) fn splice(original: &[u8], value: &JsonValue) -> serde_json::Result<Vec<u8>> {
+    let mut parser = Parser {
+        input: original,
+        pos: 0,
+    };
+
+    let lead = parser.ws();
+    let root = parser.value();
+    let trail = parser.ws();
+
+    let root = match root {
+        Some(root) if parser.pos == original.len() => root,
+        // not expected for a document that serde_json could parse
+        _ => return serde_json::to_vec(value),
+    };
+
+    let mut out = Vec::with_capacity(original.len());
+    out.extend_from_slice(lead);
+    write(&root, value, &mut out)?;
+    out.extend_from_slice(trail);
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit<F>(original: &str, transform: F) -> String
+    where
+        F: FnOnce(&mut JsonValue),
+    {
+        let mut value: JsonValue = serde_json::from_str(original).unwrap();
+        transform(&mut value);
+
+        let out = String::from_utf8(splice(original.as_bytes(), &value).unwrap()).unwrap();
+        assert_eq!(value, serde_json::from_str::<JsonValue>(&out).unwrap());
+        out
+    }
+
+    const PRETTY: &str = r#"
+{
+  "id" : 12345678901234567890,
+  "name": "café \/ bar",
+  "user": {
+    "secret": "x",
+    "tags": [ 1.0, 2e3 ]
+  },
+  "last": null
+}
+"#;
+
+    #[test]
+    fn test_splice_unchanged() {
+        assert_eq!(PRETTY, edit(PRETTY, |_| {}));
+        assert_eq!("[]", edit("[]", |_| {}));
+        assert_eq!(" { } ", edit(" { } ", |_| {}));
+    }
+
+    #[test]
+    fn test_splice_remove() {
+        let out = edit(PRETTY, |v| {
+            v["user"].as_object_mut().unwrap().shift_remove("secret");
+        });
+
+        assert_eq!(PRETTY.replace("\n    \"secret\": \"x\",", ""), out,);
+
+        let out = edit(PRETTY, |v| {
+            v.as_object_mut().unwrap().shift_remove("last");
+        });
+
+        assert_eq!(PRETTY.replace(",\n  \"last\": null", ""), out);
+
+        let out = edit(r#"{ "a": 1 }"#, |v| {
+            v.as_object_mut().unwrap().shift_remove("a");
+        });
+
+        assert_eq!("{ }", out);
+    }
+
+    #[test]
+    fn test_splice_replace() {
+        let out = edit(PRETTY, |v| v["user"]["secret"] = "redacted".into());
+
+        assert_eq!(PRETTY.replace(r#""x""#, r#""redacted""#), out);
+
+        let out = edit(PRETTY, |v| v["user"]["tags"][1] = true.into());
+
+        assert_eq!(PRETTY.replace("2e3", "true"), out);
+
+        let out = edit(PRETTY, |v| v["last"] = serde_json::json!({ "a": [1] }));
+
+        assert_eq!(PRETTY.replace("null", r#"{"a":[1]}"#), out);
+    }
+
+    #[test]
+    fn test_splice_add() {
+        let out = edit(PRETTY, |v| {
+            v["user"]["tags"].as_array_mut().unwrap().push(3.into());
+            v.as_object_mut().unwrap().insert("new".into(), "x".into());
+        });
+
+        let expected = PRETTY
+            .replace("2e3 ]", "2e3, 3 ]")
+            .replace("\"last\": null\n", "\"last\": null,\n  \"new\": \"x\"\n");
+
+        assert_eq!(expected, out);
+
+        assert_eq!(r#"{"a":1}"#, edit("{}", |v| v["a"] = 1.into()));
+    }
+
+    #[test]
+    fn test_splice_remove_elements() {
+        let out = edit("[ 1.0, 2e3, 3.50 ]", |v| {
+            v.as_array_mut().unwrap().remove(1);
+        });
+
+        assert_eq!("[ 1.0, 3.50 ]", out);
+
+        let original = r#"[
+  {"id": 1.0, "s": 1},
+  {"id": 2.0, "s": 2},
+  {"id": 3.0, "s": 3},
+  {"id": 4.0, "s": 4}
+]"#;
+        let out = edit(original, |v| {
+            let items = v.as_array_mut().unwrap();
+            items.remove(1);
+            items[1] = serde_json::json!({ "id": 5 });
+
+            for item in items {
+                item.as_object_mut().unwrap().shift_remove("s");
+            }
+        });
+
+        assert_eq!(
+            r#"[
+  {"id": 1.0},
+  {"id": 5},
+  {"id": 4.0}
+]"#,
+            out
+        );
+    }
+
+    #[test]
+    fn test_splice_rename() {
+        let out = edit(r#"{"a": [1,  2], "b": 3}"#, |v| {
+            let map = v.as_object_mut().unwrap();
+            let a = map.shift_remove("a").unwrap();
+            map.insert("c".into(), a);
+        });
+
+        assert_eq!(r#"{ "b": 3, "c": [1,2]}"#, out);
+    }
+
+    #[test]
+    fn test_splice_type_changes() {
+        assert_eq!(r#"{"a":"1"}"#, edit(r#"{"a":1}"#, |v| v["a"] = "1".into()));
+        assert_eq!(r#"{"a":""}"#, edit(r#"{"a":12}"#, |v| v["a"] = "".into()));
+        assert_eq!(
+            r#"[{"a":1}]"#,
+            edit(r#"[[1]]"#, |v| v[0] = serde_json::json!({ "a": 1 }))
+        );
+        assert_eq!(
+            r#"{"b":2}"#,
+            edit(r#"[1]"#, |v| *v = serde_json::json!({ "b": 2 }))
+        );
+    }
+}
@@ -0,0 +1,396 @@
+//! Server-sent event streams.
+//!
+//! `text/event-stream` bodies are split into events as chunks arrive, and each
+//! event is forwarded as soon as it is complete. JSON transformations apply to
+//! the data of each event that is a JSON object or array; other fields
+//! (`event`, `id`, `retry`) and comments are forwarded as they are. An event
+//! that grows larger than `max_body_size` is forwarded as it arrives,
+//! untransformed, instead of being buffered. `regex_replace` rules are applied
+//! to the data of each event, after the JSON transformations.
+//!
+//! https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+
+use log::*;
+
+use crate::ndjson::DocumentTransform;
+use crate::text::RegexReplace;
+use crate::types::Json;
+
+/// A line of an event stream, as offsets into the buffered stream.
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    start: usize,
+    /// The end of the line, before its terminator.
+    end: usize,
+    /// The start of the next line.
+    next: usize,
+}
+
+fn is_terminator(b: u8) -> bool {
+    matches!(b, b'\r' | b'\n')
+}
+
+/// Finds the line starting at `start`, looking for its terminator from
+/// `from`. Lines end with CRLF, LF or CR, so a line ending with CR at the end
+/// of the buffer is incomplete unless the stream has ended.
+fn next_line(buf: &[u8], start: usize, from: usize, eof: bool) -> Option<Line> {
+    let end = from + buf[from..].iter().position(|b| is_terminator(*b))?;
+
+    let next = match (buf[end], buf.get(end + 1)) {
+        (b'\r', Some(b'\n')) => end + 2,
+        (b'\r', None) if !eof => return None,
+        _ => end + 1,
+    };
+
+    Some(Line { start, end, next })
+}
+
+/// Splits a line into its field name and value.
+fn field(line: &[u8]) -> (&[u8], &[u8]) {
+    match line.iter().position(|b| *b == b':') {
+        Some(colon) => {
+            let value = &line[colon + 1..];
+            (&line[..colon], value.strip_prefix(b" ").unwrap_or(value))
+        }
+        None => (line, &[]),
+    }
+}
+
+pub(crate) struct EventStream {
+    transform: DocumentTransform,
+    /// The incomplete last event of the chunks so far.
+    pending: Vec<u8>,
+    /// The complete lines of the incomplete last event.
+    lines: Vec<Line>,
+    /// The start of the incomplete last line.
+    scanned: usize,
+    /// How far the incomplete last line was searched for its terminator.
+    searched: usize,
+    /// The last byte forwarded of an event that is larger than
+    /// `max_body_size`, while the rest of it is forwarded as it arrives.
+    oversize: Option<u8>,
+}
+
+impl EventStream {
+    pub(crate) fn new(
+        transformations: Vec<Json>,
+        regex_replace: Vec<RegexReplace>,
+        max_size: Option<usize>,
+    ) -> Self {
+        EventStream {
+            transform: DocumentTransform::new(transformations, regex_replace, max_size),
+            pending: vec![],
+            lines: vec![],
+            scanned: 0,
+            searched: 0,
+            oversize: None,
+        }
+    }
+
+    /// Transforms the complete events of a chunk and writes them to `out`.
+    /// The incomplete last event is kept until the next chunk, unless it is
+    /// too large to be transformed.
+    pub(crate) fn feed(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        let chunk = self.skip_oversize(chunk, out);
+
+        self.pending.extend_from_slice(chunk);
+        self.write_events(out, false);
+
+        if self.transform.exceeds_max_size(self.pending.len()) {
+            info!(
+                "event exceeds max_body_size ({} bytes so far), forwarding it as-is",
+                self.pending.len()
+            );
+            self.oversize = self.pending.last().copied();
+            out.append(&mut self.pending);
+            self.lines.clear();
+            self.scanned = 0;
+            self.searched = 0;
+        }
+    }
+
+    /// Forwards the rest of an event that is larger than `max_body_size`,
+    /// returning what follows the blank line that ends it.
+    fn skip_oversize<'a>(&mut self, chunk: &'a [u8], out: &mut Vec<u8>) -> &'a [u8] {
+        let Some(mut last) = self.oversize else {
+            return chunk;
+        };
+
+        for (i, b) in chunk.iter().enumerate() {
+            let blank = is_terminator(*b) && is_terminator(last) && (last, *b) != (b'\r', b'\n');
+            last = *b;
+
+            if blank {
+                self.oversize = None;
+                out.extend_from_slice(&chunk[..=i]);
+                return &chunk[i + 1..];
+            }
+        }
+
+        self.oversize = Some(last);
+        out.extend_from_slice(chunk);
+        &[]
+    }
+
+    /// Writes what is left of the stream. An event that is not followed by a
+    /// blank line is incomplete, and is forwarded as it is since clients
+    /// discard it.
+    pub(crate) fn finish(&mut self, out: &mut Vec<u8>) {
+        self.write_events(out, true);
+        out.append(&mut self.pending);
+    }
+
+    /// Writes the complete events that are pending. Scanning resumes where
+    /// the previous call stopped, so that a large event that arrives in many
+    /// small chunks is only scanned once.
+    fn write_events(&mut self, out: &mut Vec<u8>, eof: bool) {
+        let buf = &self.pending;
+        let mut event_start = 0;
+        let mut lines = std::mem::take(&mut self.lines);
+        let mut pos = self.scanned;
+
+        while let Some(line) = next_line(buf, pos, self.searched.max(pos), eof) {
+            pos = line.next;
+
+            if line.start < line.end {
+                lines.push(line);
+                continue;
+            }
+
+            // a blank line ends the event
+            self.write_event(&buf[event_start..line.next], event_start, &lines, out);
+            event_start = line.next;
+            lines.clear();
+        }
+
+        // a trailing CR is searched again, as it may be followed by LF
+        let searched = buf.len().saturating_sub(1).max(pos);
+
+        for line in &mut lines {
+            line.start -= event_start;
+            line.end -= event_start;
+            line.next -= event_start;
+        }
+
+        self.pending.drain(..event_start);
+        self.lines = lines;
+        self.scanned = pos - event_start;
+        self.searched = searched - event_start;
+    }
+
+    /// Writes an event, including the blank line that ends it. The offsets of
+    /// `lines` are relative to `offset`.
+    fn write_event(&self, event: &[u8], offset: usize, lines: &[Line], out: &mut Vec<u8>) {
+        let text = |line: &Line| &event[line.start - offset..line.end - offset];
+
+        let data_lines: Vec<usize> = (0..lines.len())
+            .filter(|i| field(text(&lines[*i])).0 == b"data")
+            .collect();
+
+        let Some(&first) = data_lines.first() else {
+            out.extend_from_slice(event);
+            return;
+        };
+
+        let data = data_lines
+            .iter()
+            .map(|i| field(text(&lines[*i])).1)
+            .collect::<Vec<_>>()
+            .join(&b'\n');
+
+        let Some(data) = self.transform.apply(&data) else {
+            out.extend_from_slice(event);
+            return;
+        };
+
+        // the data is written where its first line was, in the same style
+        let prefix = if text(&lines[first]).starts_with(b"data: ") {
+            &b"data: "[..]
+        } else {
+            &b"data:"[..]
+        };
+        let terminator = &event[lines[first].end - offset..lines[first].next - offset];
+
+        for (i, line) in lines.iter().enumerate() {
+            if i == first {
+                for part in data.split(|b| *b == b'\n') {
+                    out.extend_from_slice(prefix);
+                    out.extend_from_slice(part);
+                    out.extend_from_slice(terminator);
+                }
+            } else if !data_lines.contains(&i) {
+                out.extend_from_slice(&event[line.start - offset..line.next - offset]);
+            }
+        }
+
+        // the blank line
+        let end = lines.last().map_or(0, |line| line.next - offset);
+        out.extend_from_slice(&event[end..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::JsonPath;
+    use std::convert::TryFrom;
+
+    fn stream() -> EventStream {
+        stream_with_max_size(None)
+    }
+
+    fn stream_with_max_size(max_size: Option<usize>) -> EventStream {
+        EventStream::new(
+            vec![Json {
+                remove: vec![JsonPath::try_from("secret").unwrap()],
+                ..Default::default()
+            }],
+            vec![],
+            max_size,
+        )
+    }
+
+    fn feed(stream: &mut EventStream, chunks: &[&str]) -> Vec<String> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                let mut out = vec![];
+                stream.feed(chunk.as_bytes(), &mut out);
+                String::from_utf8(out).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_events() {
+        let mut stream = stream();
+
+        let out = feed(
+            &mut stream,
+            &[
+                "event: user\ndata: {\"id\": 1, \"sec",
+                "ret\": \"a\"}\nid: 1\n\n: keep-alive\n\ndata: [DONE]\n\n",
+                "data:{\"secret\": 1,\r\ndata:\"id\": 2}\r\nretry: 10\r\n\r",
+                "\n",
+            ],
+        );
+
+        assert_eq!(
+            vec![
+                "",
+                "event: user\ndata: {\"id\": 1}\nid: 1\n\n: keep-alive\n\ndata: [DONE]\n\n",
+                "",
+                "data:{\r\ndata:\"id\": 2}\r\nretry: 10\r\n\r\n",
+            ],
+            out
+        );
+    }
+
+    #[test]
+    fn test_cr_line_endings() {
+        let mut stream = stream();
+
+        let out = feed(
+            &mut stream,
+            &["data: {\"id\": 1, \"secret\": 2}\r\r", "data: {}\r"],
+        );
+        assert_eq!(vec!["", "data: {\"id\": 1}\r\r"], out);
+
+        let mut out = vec![];
+        stream.finish(&mut out);
+        assert_eq!(b"data: {}\r".to_vec(), out);
+    }
+
+    #[test]
+    fn test_untransformed_events() {
+        let mut stream = stream();
+        let input = "data: {\"id\": 1}\n\nevent: ping\n\ndata\n\ndata: not json\n\n";
+
+        assert_eq!(vec![input], feed(&mut stream, &[input]));
+
+        let mut out = vec![];
+        stream.finish(&mut out);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_finish_incomplete_event() {
+        let mut stream = stream();
+
+        assert_eq!(vec![""], feed(&mut stream, &["data: {\"secret\": 1}\n"]));
+
+        let mut out = vec![];
+        stream.finish(&mut out);
+        assert_eq!(b"data: {\"secret\": 1}\n".to_vec(), out);
+    }
+
+    #[test]
+    fn test_event_in_small_chunks() {
+        let mut stream = stream();
+        let input = "id: 1\r\ndata: {\"secret\": 1,\r\ndata: \"id\": 2}\r\n\r\n";
+
+        let chunks: Vec<String> = input.chars().map(String::from).collect();
+        let chunks: Vec<&str> = chunks.iter().map(String::as_str).collect();
+        let out = feed(&mut stream, &chunks);
+
+        assert_eq!("id: 1\r\ndata: {\r\ndata: \"id\": 2}\r\n\r\n", out.concat());
+        assert!(out[..out.len() - 1].iter().all(String::is_empty));
+        assert_eq!(0, stream.scanned);
+    }
+
+    #[test]
+    fn test_regex_replace() {
+        let mut stream = EventStream::new(
+            vec![],
+            vec![RegexReplace::try_from(r"\.internal\b => .example.com").unwrap()],
+            None,
+        );
+
+        // only the data is matched, event by event
+        let out = feed(
+            &mut stream,
+            &[
+                "event: a.internal\ndata: a.inter",
+                "nal\ndata: b.internal\n\ndata: c.internal\n\n",
+            ],
+        );
+
+        assert_eq!(
+            vec![
+                "",
+                "event: a.internal\ndata: a.example.com\ndata: b.example.com\n\n\
+                 data: c.example.com\n\n",
+            ],
+            out
+        );
+    }
+
+    #[test]
+    fn test_oversized_events() {
+        let mut stream = stream_with_max_size(Some(32));
+
+        let out = feed(
+            &mut stream,
+            &[
+                "data: {\"secret\": 1, ",
+                "\"padding\": \"aaaaaaaa\"}\r",
+                "\n\r",
+                "\ndata: {\"id\": 1, \"secret\": 2}\n\n",
+                "data: {\"secret\": 1, \"padding\": \"aaaaaaaa\"}\n\n",
+            ],
+        );
+
+        assert_eq!(
+            vec![
+                "",
+                "data: {\"secret\": 1, \"padding\": \"aaaaaaaa\"}\r",
+                "\n\r",
+                "\ndata: {\"id\": 1}\n\n",
+                "data: {\"secret\": 1, \"padding\": \"aaaaaaaa\"}\n\n",
+            ],
+            out
+        );
+        assert!(stream.pending.is_empty());
+        assert_eq!(None, stream.oversize);
+    }
+}
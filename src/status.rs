@@ -0,0 +1,154 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidStatusRange(String);
+
+impl fmt::Display for InvalidStatusRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid status code or range => {:?}", self.0)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StatusInput {
+    Code(u16),
+    Range(String),
+}
+
+/// An inclusive range of HTTP status codes, written as a single code (`404`),
+/// a class (`2xx`) or an explicit range (`500-599`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "StatusInput")]
+pub(crate) struct StatusRange {
+    min: u16,
+    max: u16,
+}
+
+fn parse_code(s: &str) -> Option<u16> {
+    s.trim()
+        .parse()
+        .ok()
+        .filter(|code| (100..=599).contains(code))
+}
+
+impl TryFrom<&str> for StatusRange {
+    type Error = InvalidStatusRange;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let err = || InvalidStatusRange(input.to_owned());
+        let s = input.trim().to_lowercase();
+
+        if let Some(class) = s.strip_suffix("xx") {
+            let class: u16 = match class {
+                "1" | "2" | "3" | "4" | "5" => class.parse().map_err(|_| err())?,
+                _ => return Err(err()),
+            };
+
+            return Ok(StatusRange {
+                min: class * 100,
+                max: class * 100 + 99,
+            });
+        }
+
+        let (min, max) = match s.split_once('-') {
+            Some((min, max)) => (parse_code(min), parse_code(max)),
+            None => (parse_code(&s), parse_code(&s)),
+        };
+
+        match (min, max) {
+            (Some(min), Some(max)) if min <= max => Ok(StatusRange { min, max }),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl TryFrom<StatusInput> for StatusRange {
+    type Error = InvalidStatusRange;
+
+    fn try_from(input: StatusInput) -> Result<Self, Self::Error> {
+        match input {
+            StatusInput::Code(code) => StatusRange::try_from(code.to_string().as_str()),
+            StatusInput::Range(s) => StatusRange::try_from(s.as_str()),
+        }
+    }
+}
+
+impl StatusRange {
+    fn contains(&self, status: u16) -> bool {
+        (self.min..=self.max).contains(&status)
+    }
+}
+
+/// A list of status ranges. An empty filter matches every response.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct StatusFilter(Vec<StatusRange>);
+
+impl StatusFilter {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn matches(&self, status: Option<u16>) -> bool {
+        self.0.is_empty() || status.is_some_and(|s| self.0.iter().any(|r| r.contains(s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> StatusRange {
+        StatusRange::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn test_status_range_try_from() {
+        assert_eq!(StatusRange { min: 200, max: 299 }, range("2xx"));
+        assert_eq!(StatusRange { min: 500, max: 599 }, range("5XX"));
+        assert_eq!(StatusRange { min: 404, max: 404 }, range("404"));
+        assert_eq!(StatusRange { min: 500, max: 503 }, range("500-503"));
+        assert_eq!(StatusRange { min: 500, max: 503 }, range(" 500 - 503 "));
+
+        for input in [
+            "", "6xx", "0xx", "xx", "2x", "99", "600", "503-500", "abc", "1-2-3",
+        ] {
+            assert!(StatusRange::try_from(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_status_filter_deserialize() {
+        let filter: StatusFilter = serde_json::from_str(r#"["2xx", 404, "500-502"]"#).unwrap();
+
+        assert_eq!(
+            StatusFilter(vec![
+                StatusRange { min: 200, max: 299 },
+                StatusRange { min: 404, max: 404 },
+                StatusRange { min: 500, max: 502 },
+            ]),
+            filter
+        );
+
+        assert!(serde_json::from_str::<StatusFilter>(r#"["nope"]"#).is_err());
+        assert!(serde_json::from_str::<StatusFilter>("[700]").is_err());
+    }
+
+    #[test]
+    fn test_status_filter_matches() {
+        let filter: StatusFilter = serde_json::from_str(r#"["2xx", 404]"#).unwrap();
+
+        assert!(filter.matches(Some(200)));
+        assert!(filter.matches(Some(299)));
+        assert!(filter.matches(Some(404)));
+        assert!(!filter.matches(Some(403)));
+        assert!(!filter.matches(None));
+
+        assert!(StatusFilter::default().matches(Some(500)));
+        assert!(StatusFilter::default().matches(None));
+    }
+}
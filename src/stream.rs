@@ -0,0 +1,891 @@
+//! Streaming JSON object transformations.
+//!
+//! When the only JSON operations are `remove`, `rename` and `replace` of
+//! top-level object members, the body does not need to be buffered: members
+//! can be dropped, renamed or have their value swapped out as chunks arrive.
+//! Everything else about the document (member order, whitespace, number
+//! formatting) is forwarded as-is, and the result is the same as that of the
+//! buffered transformations.
+//!
+//! A renamed member keeps its position. A member whose final name is also the
+//! destination of a rename is held back, along with the members after it,
+//! until the rename's source is found (which overwrites it) or the object
+//! ends. Once more than `max_body_size` bytes are held back, they are written
+//! and the rest of the body is forwarded untransformed.
+//!
+//! Unlike the buffered transformations, the stream cannot know that a document
+//! is valid before it has been read in full. When it finds that it is not, the
+//! members before the error have already been transformed, and the rest of the
+//! body is forwarded as-is, whereas a buffered invalid body is left untouched.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::Range;
+
+use serde_json::Value as JsonValue;
+
+use crate::types::Json;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    Remove(String),
+    Rename(String, String),
+    Replace(String, JsonValue),
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome<'a> {
+    Remove,
+    Keep {
+        name: Cow<'a, str>,
+        value: Option<&'a JsonValue>,
+        renamed: bool,
+    },
+}
+
+/// The combined operations of one or more `Json` transformations, in the
+/// order they are applied.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct StreamPlan {
+    ops: Vec<Op>,
+    /// Final names of renamed members.
+    rename_targets: HashSet<String>,
+}
+
+impl StreamPlan {
+    /// Builds a plan from a sequence of transformations, if they can be
+    /// applied without buffering the body.
+    pub(crate) fn new<'a, I>(transformations: I) -> Option<StreamPlan>
+    where
+        I: IntoIterator<Item = &'a Json>,
+    {
+        let mut plan = StreamPlan::default();
+
+        for tx in transformations {
+            if !tx.add.is_empty() || !tx.append.is_empty() || tx.merge.is_some() {
+                return None;
+            }
+
+            if !tx.patch.is_empty() {
+                return None;
+            }
+
+            for path in &tx.remove {
+                plan.ops.push(Op::Remove(path.as_key()?.to_owned()));
+            }
+
+            for (from, to) in &tx.rename {
+                plan.ops.push(Op::Rename(
+                    from.as_key()?.to_owned(),
+                    to.as_key()?.to_owned(),
+                ));
+            }
+
+            for (path, value) in &tx.replace {
+                plan.ops
+                    .push(Op::Replace(path.as_key()?.to_owned(), value.clone()));
+            }
+        }
+
+        // Chained renames (`a:b` followed by `b:c`) and multiple renames to the
+        // same destination could produce duplicate members.
+        let mut sources = HashSet::new();
+        let mut destinations = HashSet::new();
+
+        for op in &plan.ops {
+            if let Op::Rename(from, to) = op {
+                if !destinations.insert(to.as_str()) {
+                    return None;
+                }
+                sources.insert(from.as_str());
+            }
+        }
+
+        if !sources.is_disjoint(&destinations) {
+            return None;
+        }
+
+        let targets: HashSet<String> = plan
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Rename(from, _) => match plan.outcome(from) {
+                    Outcome::Keep {
+                        name,
+                        renamed: true,
+                        ..
+                    } => Some(name.into_owned()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+
+        plan.rename_targets = targets;
+
+        Some(plan)
+    }
+
+    /// Returns what becomes of a top-level member.
+    fn outcome<'a>(&'a self, key: &'a str) -> Outcome<'a> {
+        let mut name = Cow::Borrowed(key);
+        let mut value = None;
+        let mut renamed = false;
+
+        for op in &self.ops {
+            match op {
+                Op::Remove(k) if *k == name => return Outcome::Remove,
+                Op::Rename(from, to) if *from == name => {
+                    name = Cow::Borrowed(to);
+                    renamed = true;
+                }
+                Op::Replace(k, v) if *k == name => value = Some(v),
+                _ => {}
+            }
+        }
+
+        Outcome::Keep {
+            name,
+            value,
+            renamed,
+        }
+    }
+}
+
+/// The body is not a JSON object, so it must be buffered instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct NotAnObject;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    BeforeKey,
+    Key,
+    BeforeColon,
+    BeforeValue,
+    Value,
+    AfterValue,
+    End,
+    Passthrough,
+}
+
+/// Where the member being read goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sink {
+    Emit,
+    Skip,
+    Hold(usize),
+}
+
+#[derive(Debug, Clone, Default)]
+struct ValueScan {
+    depth: usize,
+    in_string: bool,
+    escape: bool,
+}
+
+/// A member that cannot be written yet, because it comes after a member that
+/// a rename may still overwrite.
+#[derive(Debug, Clone)]
+struct Held {
+    /// The name of the member, if a rename may overwrite it.
+    conflict: Option<String>,
+    bytes: Vec<u8>,
+    /// Whitespace after the value, before the following comma.
+    trail: Vec<u8>,
+}
+
+impl Held {
+    fn len(&self) -> usize {
+        self.bytes.len() + self.trail.len()
+    }
+}
+
+fn is_ws(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Transforms a JSON object body chunk by chunk.
+///
+/// Members are written like `splice` writes them: each kept member keeps the
+/// whitespace before its name, around its colon and before the following
+/// comma, so that removing a member only drops the bytes that belong to it.
+#[derive(Debug, Clone)]
+pub(crate) struct JsonStream {
+    plan: StreamPlan,
+    state: State,
+    /// Bytes that have been read but not yet written: leading whitespace
+    /// before the object, or the member currently being read up to its value.
+    pending: Vec<u8>,
+    /// The range of the quoted member name within `pending`.
+    key: Range<usize>,
+    key_escape: bool,
+    value: ValueScan,
+    sink: Sink,
+    /// Whether the value of the current member has been replaced.
+    replaced: bool,
+    /// Whitespace after the current value.
+    trail: Vec<u8>,
+    /// Whitespace after the value of the last member written, which goes
+    /// before the comma that precedes the next one.
+    sep: Vec<u8>,
+    after_comma: bool,
+    members: usize,
+    produced: HashSet<String>,
+    held: Vec<Held>,
+    /// The size of the held members.
+    held_size: usize,
+    /// The most bytes that are held back, along with `pending`.
+    max_held: Option<usize>,
+    /// How much was held back when the limit was exceeded, if it was.
+    oversize: Option<usize>,
+}
+
+impl JsonStream {
+    pub(crate) fn new(plan: StreamPlan, max_held: Option<usize>) -> Self {
+        JsonStream {
+            plan,
+            state: State::Start,
+            pending: vec![],
+            key: 0..0,
+            key_escape: false,
+            value: ValueScan::default(),
+            sink: Sink::Emit,
+            replaced: false,
+            trail: vec![],
+            sep: vec![],
+            after_comma: false,
+            members: 0,
+            produced: HashSet::new(),
+            held: vec![],
+            held_size: 0,
+            max_held,
+            oversize: None,
+        }
+    }
+
+    /// Returns how many bytes were held back when `max_held` was exceeded, if
+    /// it was. The rest of the body is then forwarded as-is.
+    pub(crate) fn oversize(&self) -> Option<usize> {
+        self.oversize
+    }
+
+    /// Transforms the next chunk of the body, appending the output to `out`.
+    ///
+    /// `NotAnObject` can only be returned before anything has been written to
+    /// `out`, so the caller can still fall back to buffering the body.
+    pub(crate) fn feed(&mut self, chunk: &[u8], out: &mut Vec<u8>) -> Result<(), NotAnObject> {
+        let mut i = 0;
+
+        while i < chunk.len() {
+            let b = chunk[i];
+
+            if self.state != State::Passthrough && self.exceeds_max_held() {
+                self.oversize = Some(self.held_size + self.pending.len());
+                self.passthrough(out);
+            }
+
+            match self.state {
+                State::Start => match b {
+                    b'{' => {
+                        out.append(&mut self.pending);
+                        out.push(b);
+                        self.state = State::BeforeKey;
+                    }
+                    b if is_ws(b) => self.pending.push(b),
+                    _ => return Err(NotAnObject),
+                },
+                State::BeforeKey => match b {
+                    b'"' => {
+                        self.key = self.pending.len()..self.pending.len();
+                        self.pending.push(b);
+                        self.key_escape = false;
+                        self.state = State::Key;
+                    }
+                    b'}' if !self.after_comma => {
+                        let close = std::mem::take(&mut self.pending);
+                        self.close(close, out);
+                    }
+                    b if is_ws(b) => self.pending.push(b),
+                    _ => {
+                        self.passthrough(out);
+                        continue;
+                    }
+                },
+                State::Key => {
+                    self.pending.push(b);
+
+                    if self.key_escape {
+                        self.key_escape = false;
+                    } else if b == b'\\' {
+                        self.key_escape = true;
+                    } else if b == b'"' {
+                        self.key.end = self.pending.len();
+                        self.state = State::BeforeColon;
+                    }
+                }
+                State::BeforeColon => match b {
+                    b':' => {
+                        self.pending.push(b);
+                        self.state = State::BeforeValue;
+                    }
+                    b if is_ws(b) => self.pending.push(b),
+                    _ => {
+                        self.passthrough(out);
+                        continue;
+                    }
+                },
+                State::BeforeValue => {
+                    if is_ws(b) {
+                        self.pending.push(b);
+                    } else if self.begin_member(out) {
+                        self.value = ValueScan::default();
+                        self.state = State::Value;
+                        // the first byte of the value is processed below
+                        continue;
+                    } else {
+                        self.passthrough(out);
+                        continue;
+                    }
+                }
+                State::Value => {
+                    if !self.scan_value(b, out) {
+                        // the byte after a scalar value ends it, and is
+                        // processed again as a separator
+                        self.state = State::AfterValue;
+                        continue;
+                    }
+                }
+                State::AfterValue => match b {
+                    b',' => {
+                        self.end_member();
+                        self.after_comma = true;
+                        self.state = State::BeforeKey;
+                    }
+                    b'}' => {
+                        let close = std::mem::take(&mut self.trail);
+                        self.close(close, out);
+                    }
+                    b if is_ws(b) => self.trail.push(b),
+                    _ => {
+                        self.passthrough(out);
+                        continue;
+                    }
+                },
+                State::End | State::Passthrough => out.push(b),
+            }
+
+            i += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes anything still pending at the end of the body. This only
+    /// happens for truncated or invalid documents.
+    pub(crate) fn finish(&mut self, out: &mut Vec<u8>) {
+        if !matches!(self.state, State::End | State::Passthrough) {
+            self.flush(out);
+        }
+    }
+
+    /// Returns whether too much is held back, and the rest of the body can be
+    /// forwarded as-is: not while the original value of a removed or replaced
+    /// member is being skipped.
+    fn exceeds_max_held(&self) -> bool {
+        let skipping = self.state == State::Value && (self.sink == Sink::Skip || self.replaced);
+
+        !skipping
+            && self
+                .max_held
+                .is_some_and(|max| self.held_size + self.pending.len() > max)
+    }
+
+    /// Gives up on an invalid document, forwarding the rest of it as-is.
+    fn passthrough(&mut self, out: &mut Vec<u8>) {
+        self.flush(out);
+        self.state = State::Passthrough;
+    }
+
+    /// Writes everything that has been read but not written yet.
+    fn flush(&mut self, out: &mut Vec<u8>) {
+        if self.state != State::Start {
+            self.write_held(self.held.len(), out);
+
+            if self.sink != Sink::Skip {
+                out.append(&mut self.trail);
+            }
+
+            if std::mem::take(&mut self.after_comma) && self.members > 0 {
+                out.append(&mut self.sep);
+                out.push(b',');
+            }
+        }
+
+        out.append(&mut self.pending);
+    }
+
+    fn write(&mut self, b: u8, out: &mut Vec<u8>) {
+        if self.replaced {
+            return;
+        }
+
+        match self.sink {
+            Sink::Emit => out.push(b),
+            Sink::Skip => {}
+            Sink::Hold(i) => {
+                self.held[i].bytes.push(b);
+                self.held_size += 1;
+            }
+        }
+    }
+
+    /// Writes the separator before a member.
+    fn start_member(&mut self, out: &mut Vec<u8>) {
+        if self.members > 0 {
+            out.append(&mut self.sep);
+            out.push(b',');
+        }
+
+        self.members += 1;
+    }
+
+    /// Writes the first `count` held members.
+    fn write_held(&mut self, count: usize, out: &mut Vec<u8>) {
+        let held: Vec<Held> = self.held.drain(..count).collect();
+        self.held_size = self.held.iter().map(Held::len).sum();
+
+        for held in held {
+            self.start_member(out);
+            out.extend(held.bytes);
+            self.sep = held.trail;
+        }
+    }
+
+    /// Processes a byte of a member value. Returns false if the byte is not
+    /// part of the value.
+    fn scan_value(&mut self, b: u8, out: &mut Vec<u8>) -> bool {
+        let scan = &mut self.value;
+
+        if scan.in_string {
+            if scan.escape {
+                scan.escape = false;
+            } else if b == b'\\' {
+                scan.escape = true;
+            } else if b == b'"' {
+                scan.in_string = false;
+
+                if scan.depth == 0 {
+                    self.state = State::AfterValue;
+                }
+            }
+
+            self.write(b, out);
+            return true;
+        }
+
+        match b {
+            b'"' => scan.in_string = true,
+            b'{' | b'[' => scan.depth += 1,
+            b'}' | b']' if scan.depth > 0 => {
+                scan.depth -= 1;
+
+                if scan.depth == 0 {
+                    self.state = State::AfterValue;
+                }
+            }
+            b if scan.depth == 0 && (is_ws(b) || b == b',' || b == b'}') => return false,
+            _ => {}
+        }
+
+        self.write(b, out);
+        true
+    }
+
+    /// Decides what to do with the member whose value is about to be read.
+    /// Returns false if its name is invalid.
+    fn begin_member(&mut self, out: &mut Vec<u8>) -> bool {
+        let Ok(key) = serde_json::from_slice::<String>(&self.pending[self.key.clone()]) else {
+            return false;
+        };
+
+        self.after_comma = false;
+        let mut prefix = std::mem::take(&mut self.pending);
+
+        let (name, value, renamed) = match self.plan.outcome(&key) {
+            Outcome::Remove => {
+                self.sink = Sink::Skip;
+                return true;
+            }
+            Outcome::Keep {
+                name,
+                value,
+                renamed,
+            } => (name.into_owned(), value.cloned(), renamed),
+        };
+
+        // an untouched name is written exactly as it was received
+        if name != key {
+            let quoted = serde_json::to_vec(&name).expect("strings always serialize");
+            prefix.splice(self.key.clone(), quoted);
+        }
+
+        self.replaced = value.is_some();
+        if let Some(value) = &value {
+            prefix.extend(serde_json::to_vec(value).expect("JSON values always serialize"));
+        }
+
+        if renamed {
+            // a held member with the same name is overwritten, and the
+            // members after it no longer need to wait
+            self.held
+                .retain(|held| held.conflict.as_ref() != Some(&name));
+            self.held_size = self.held.iter().map(Held::len).sum();
+            self.produced.insert(name.clone());
+        }
+
+        let resolved = self
+            .held
+            .iter()
+            .position(|held| held.conflict.is_some())
+            .unwrap_or(self.held.len());
+        self.write_held(resolved, out);
+
+        let conflict = !renamed && self.plan.rename_targets.contains(&name);
+
+        self.sink = if conflict && self.produced.contains(&name) {
+            // already overwritten by a renamed member
+            Sink::Skip
+        } else if conflict || !self.held.is_empty() {
+            self.held_size += prefix.len();
+            self.held.push(Held {
+                conflict: conflict.then_some(name),
+                bytes: prefix,
+                trail: vec![],
+            });
+            Sink::Hold(self.held.len() - 1)
+        } else {
+            self.start_member(out);
+            out.extend(prefix);
+            Sink::Emit
+        };
+
+        true
+    }
+
+    /// Ends the current member at the comma that follows it.
+    fn end_member(&mut self) {
+        let trail = std::mem::take(&mut self.trail);
+
+        match self.sink {
+            Sink::Emit => self.sep = trail,
+            Sink::Hold(i) => {
+                self.held_size += trail.len();
+                self.held[i].trail = trail;
+            }
+            Sink::Skip => {}
+        }
+
+        self.replaced = false;
+    }
+
+    /// Closes the object, writing any held members that were not overwritten
+    /// by a rename.
+    fn close(&mut self, close: Vec<u8>, out: &mut Vec<u8>) {
+        self.write_held(self.held.len(), out);
+
+        out.extend(close);
+        out.push(b'}');
+        self.state = State::End;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::JsonPath;
+    use serde_json::json;
+
+    fn path(s: &str) -> JsonPath {
+        JsonPath::try_from(s).unwrap()
+    }
+
+    fn stream(plan: &StreamPlan, body: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut stream = JsonStream::new(plan.clone(), None);
+        let mut out = vec![];
+
+        for chunk in body.chunks(chunk_size) {
+            stream.feed(chunk, &mut out).unwrap();
+        }
+        stream.finish(&mut out);
+
+        out
+    }
+
+    /// Checks that streaming gives the same result as the buffered
+    /// transformation, including the order of members, whatever the chunk
+    /// size.
+    fn assert_same_as_buffered(txs: &[Json], body: &str) {
+        let plan = StreamPlan::new(txs).unwrap();
+
+        let mut expect: JsonValue = serde_json::from_str(body).unwrap();
+        for tx in txs {
+            tx.transform_body(&mut expect);
+        }
+        let expect = serde_json::to_string(&expect).unwrap();
+
+        for chunk_size in 1..=body.len() {
+            let out = stream(&plan, body.as_bytes(), chunk_size);
+            let found: JsonValue = serde_json::from_slice(&out).unwrap_or_else(|e| {
+                panic!("{}: {:?}", e, String::from_utf8_lossy(&out));
+            });
+            assert_eq!(
+                expect,
+                serde_json::to_string(&found).unwrap(),
+                "chunk size: {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn test_plan_streamable() {
+        let tx = Json {
+            remove: vec![path("a")],
+            rename: vec![(path("b"), path("c"))],
+            replace: vec![(path("d"), json!(1))],
+            ..Default::default()
+        };
+        assert!(StreamPlan::new([&tx]).is_some());
+        assert!(StreamPlan::new([]).is_some());
+
+        for tx in [
+            Json {
+                remove: vec![path("a.b")],
+                ..Default::default()
+            },
+            Json {
+                remove: vec![path("*")],
+                ..Default::default()
+            },
+            Json {
+                add: vec![(path("a"), json!(1))],
+                ..Default::default()
+            },
+            Json {
+                append: vec![(path("a"), json!(1))],
+                ..Default::default()
+            },
+            Json {
+                merge: Some(json!({})),
+                ..Default::default()
+            },
+            Json {
+                rename: vec![(path("a"), path("b")), (path("b"), path("c"))],
+                ..Default::default()
+            },
+            Json {
+                rename: vec![(path("a"), path("c")), (path("b"), path("c"))],
+                ..Default::default()
+            },
+        ] {
+            assert!(StreamPlan::new([&tx]).is_none(), "{:?}", tx);
+        }
+    }
+
+    #[test]
+    fn test_stream_preserves_untouched_bytes() {
+        let tx = Json {
+            remove: vec![path("secret")],
+            rename: vec![(path("old"), path("new"))],
+            replace: vec![(path("n"), json!("x"))],
+            ..Default::default()
+        };
+        let plan = StreamPlan::new([&tx]).unwrap();
+
+        let bodies: [(&[u8], &[u8]); 3] = [
+            (
+                br#" { "secret" : {"a": [1, "}"]}, "keep": [ 1.0, 2e3 ],"old":"v\"",  "n" : 5, "zA": null } "#,
+                br#" { "keep": [ 1.0, 2e3 ],"new":"v\"",  "n" : "x", "zA": null } "#,
+            ),
+            (
+                b"{\n  \"keep\": [ 1.0 ],\n  \"secret\": 1 ,\n  \"old\" :\t\"v\",\n  \"n\": 5\n}\n",
+                b"{\n  \"keep\": [ 1.0 ],\n  \"new\" :\t\"v\",\n  \"n\": \"x\"\n}\n",
+            ),
+            (
+                b"{\r\n\t\"keep\": 1 ,\r\n\t\"secret\": 2\r\n}",
+                b"{\r\n\t\"keep\": 1\r\n}",
+            ),
+        ];
+
+        for (body, expect) in bodies {
+            for chunk_size in 1..=body.len() {
+                assert_eq!(
+                    String::from_utf8_lossy(expect),
+                    String::from_utf8_lossy(&stream(&plan, body, chunk_size)),
+                    "chunk size: {}",
+                    chunk_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_same_as_splice() {
+        let tx = Json {
+            remove: vec![path("secret")],
+            replace: vec![(path("n"), json!("x"))],
+            ..Default::default()
+        };
+        let plan = StreamPlan::new([&tx]).unwrap();
+
+        let body = "{\n  \"secret\": { \"a\": 1 },\n  \"keep\": [ 1.0, 2e3 ],\n  \"n\" : 5\n}";
+
+        let mut value: JsonValue = serde_json::from_str(body).unwrap();
+        tx.transform_body(&mut value);
+        let expect = crate::splice::splice(body.as_bytes(), &value).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(&expect),
+            String::from_utf8_lossy(&stream(&plan, body.as_bytes(), 3))
+        );
+    }
+
+    #[test]
+    fn test_stream_same_as_buffered() {
+        let txs = [
+            Json {
+                remove: vec![path("password"), path("gone")],
+                rename: vec![(path("name"), path("display_name"))],
+                replace: vec![(path("status"), json!({ "code": 1 }))],
+                ..Default::default()
+            },
+            Json {
+                remove: vec![path("display_name_old")],
+                replace: vec![(path("display_name"), json!("hidden"))],
+                ..Default::default()
+            },
+        ];
+
+        assert_same_as_buffered(
+            &txs,
+            r#"{"id":1,"password":"p","name":"alice","status":true,"tags":["a",{"b":null}]}"#,
+        );
+        assert_same_as_buffered(&txs, r#"{}"#);
+        assert_same_as_buffered(&txs, r#"{"password":1}"#);
+        assert_same_as_buffered(&txs, r#"{"status":false,"password":1}"#);
+    }
+
+    #[test]
+    fn test_stream_rename_overwrites() {
+        let txs = [Json {
+            rename: vec![(path("a"), path("b"))],
+            ..Default::default()
+        }];
+
+        // the existing member is overwritten whether it comes before or after
+        // the renamed one, and kept when there is nothing to rename
+        assert_same_as_buffered(&txs, r#"{"b":1,"a":2,"c":3}"#);
+        assert_same_as_buffered(&txs, r#"{"a":2,"b":1,"c":3}"#);
+        assert_same_as_buffered(&txs, r#"{"b":1,"x":0,"a":2,"c":3}"#);
+        assert_same_as_buffered(&txs, r#"{"x":0,"a":2,"y":1}"#);
+        assert_same_as_buffered(&txs, r#"{"b":1,"c":3}"#);
+        assert_same_as_buffered(&txs, r#"{"b":1}"#);
+
+        let plan = StreamPlan::new(&txs).unwrap();
+        assert_eq!(
+            br#"{ "x": 0, "b": 2 }"#.to_vec(),
+            stream(&plan, br#"{ "b": 1, "x": 0, "a": 2 }"#, 1)
+        );
+    }
+
+    #[test]
+    fn test_stream_not_an_object() {
+        let plan = StreamPlan::default();
+
+        for body in [&b"  [1, 2]"[..], b"\"a\"", b"1"] {
+            let mut stream = JsonStream::new(plan.clone(), None);
+            let mut out = vec![];
+
+            assert_eq!(Err(NotAnObject), stream.feed(body, &mut out));
+            assert!(out.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_stream_invalid_passthrough() {
+        let tx = Json {
+            remove: vec![path("a")],
+            ..Default::default()
+        };
+        let plan = StreamPlan::new([&tx]).unwrap();
+
+        assert_eq!(
+            br#"{"b":1 oops "a": 2}"#.to_vec(),
+            stream(&plan, br#"{"b":1 oops "a": 2}"#, 4)
+        );
+        assert_eq!(
+            br#"{"b":1 , }"#.to_vec(),
+            stream(&plan, br#"{"b":1 , }"#, 4)
+        );
+        assert_eq!(br#"{"b":1"#.to_vec(), stream(&plan, br#"{"b":1"#, 2));
+        assert_eq!(br#"{ "b"#.to_vec(), stream(&plan, br#"{"a":1, "b"#, 2));
+
+        // members before the error are transformed, unlike in the buffered
+        // transformation, which leaves an invalid body untouched
+        assert_eq!(
+            br#"{ "b": 2 oops}"#.to_vec(),
+            stream(&plan, br#"{"a": 1, "b": 2 oops}"#, 3)
+        );
+    }
+
+    #[test]
+    fn test_stream_held_limit() {
+        let txs = [Json {
+            remove: vec![path("s")],
+            rename: vec![(path("a"), path("b"))],
+            ..Default::default()
+        }];
+        let plan = StreamPlan::new(&txs).unwrap();
+
+        // once too much is held back, the rest is forwarded untransformed
+        let cases = [
+            (
+                r#"{"b": 1, "x": "0123456789abcdef", "a": 2}"#,
+                r#"{"b": 1, "x": "0123456789abcdef", "a": 2}"#,
+                true,
+            ),
+            (
+                r#"{"b": 1, "s": "0123456789abcdefghij", "t": "0123456789abcdef", "a": 2}"#,
+                r#"{"b": 1, "t": "0123456789abcdef", "a": 2}"#,
+                true,
+            ),
+            // nothing is held back
+            (
+                r#"{"x": "0123456789abcdef", "a": 2, "s": 3}"#,
+                r#"{"x": "0123456789abcdef", "b": 2}"#,
+                false,
+            ),
+        ];
+
+        for (body, expect, oversize) in cases {
+            for chunk_size in 1..=body.len() {
+                let mut stream = JsonStream::new(plan.clone(), Some(16));
+                let mut out = vec![];
+
+                for chunk in body.as_bytes().chunks(chunk_size) {
+                    stream.feed(chunk, &mut out).unwrap();
+                }
+                stream.finish(&mut out);
+
+                assert_eq!(
+                    expect,
+                    String::from_utf8(out).unwrap(),
+                    "chunk size: {}",
+                    chunk_size
+                );
+                assert_eq!(oversize, stream.oversize().is_some());
+            }
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Value templates.
+//!
+//! Templates are strings with embedded `$(...)` expressions that are evaluated
+//! for each request, e.g. `id=$(request.header.x-request-id)`. The supported
+//! expressions are:
+//!
+//! * `request.header.<name>`: a request header
+//! * `request.method`: the request method
+//! * `request.path`: the request path, without the query string
+//! * `request.query`: the raw request query string
+//! * `request.query.<name>`: a (decoded) request query string argument
+//! * `request.authority`: the request authority (host and optional port)
+//! * `response.header.<name>`: a response header
+//! * `response.status`: the response status code
+//! * `request.<name>`: any other request property (e.g. `request.id`)
+//! * `property.<path>`: an arbitrary host property (e.g. `property.route_name`)
+//!
+//! Expressions that cannot be resolved evaluate to an empty string. A literal
+//! `$` is written as `$$`, e.g. `cost $$(USD)` or `$$$(response.header.x-price)`
+//! for a price.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Variable {
+    RequestHeader(String),
+    RequestMethod,
+    RequestPath,
+    RequestQuery,
+    RequestAuthority,
+    QueryArg(String),
+    ResponseHeader(String),
+    ResponseStatus,
+    Property(Vec<String>),
+}
+
+impl TryFrom<&str> for Variable {
+    type Error = &'static str;
+
+    fn try_from(expr: &str) -> Result<Self, Self::Error> {
+        let expr = expr.trim();
+
+        let non_empty = |name: &str| {
+            if name.is_empty() {
+                Err("empty name")
+            } else {
+                Ok(name.to_lowercase())
+            }
+        };
+
+        if let Some(name) = expr.strip_prefix("request.header.") {
+            return Ok(Variable::RequestHeader(non_empty(name)?));
+        }
+
+        match expr {
+            "request.method" => return Ok(Variable::RequestMethod),
+            "request.path" => return Ok(Variable::RequestPath),
+            "request.query" => return Ok(Variable::RequestQuery),
+            "request.authority" => return Ok(Variable::RequestAuthority),
+            _ => {}
+        }
+
+        if let Some(name) = expr.strip_prefix("request.query.") {
+            if name.is_empty() {
+                return Err("empty name");
+            }
+
+            // query string argument names are case-sensitive
+            return Ok(Variable::QueryArg(name.to_owned()));
+        }
+
+        if let Some(name) = expr.strip_prefix("response.header.") {
+            return Ok(Variable::ResponseHeader(non_empty(name)?));
+        }
+
+        if expr == "response.status" {
+            return Ok(Variable::ResponseStatus);
+        }
+
+        let path = if let Some(path) = expr.strip_prefix("property.") {
+            path.to_owned()
+        } else if expr.starts_with("request.") {
+            expr.to_owned()
+        } else {
+            return Err("unknown template variable");
+        };
+
+        if path.split('.').any(str::is_empty) {
+            return Err("empty property name");
+        }
+
+        Ok(Variable::Property(
+            path.split('.').map(String::from).collect(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidTemplate {
+    template: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for InvalidTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid template {:?}: {}", self.template, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidTemplate {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Template(Vec<Part>);
+
+impl TryFrom<&str> for Template {
+    type Error = InvalidTemplate;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let err = |reason| InvalidTemplate {
+            template: input.to_owned(),
+            reason,
+        };
+
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut rest = input;
+
+        while let Some(dollar) = rest.find('$') {
+            literal.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+
+            // `$$` is an escaped `$`
+            if let Some(after) = rest.strip_prefix('$') {
+                literal.push('$');
+                rest = after;
+                continue;
+            }
+
+            let Some(expr) = rest.strip_prefix('(') else {
+                literal.push('$');
+                continue;
+            };
+
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+
+            let end = expr
+                .find(')')
+                .ok_or_else(|| err("unterminated expression"))?;
+
+            parts.push(Part::Variable(
+                Variable::try_from(&expr[..end]).map_err(err)?,
+            ));
+
+            rest = &expr[end + 1..];
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template(parts))
+    }
+}
+
+impl Template {
+    /// Returns the value of a template without variables.
+    pub(crate) fn as_literal(&self) -> Option<String> {
+        match self.0.as_slice() {
+            [] => Some(String::new()),
+            [Part::Literal(s)] => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns every variable referenced by the template.
+    pub(crate) fn variables(&self) -> impl Iterator<Item = &Variable> {
+        self.0.iter().filter_map(|part| match part {
+            Part::Variable(v) => Some(v),
+            Part::Literal(_) => None,
+        })
+    }
+
+    /// Evaluates the template, using `resolve` to look up variables.
+    pub(crate) fn render<F>(&self, resolve: F) -> String
+    where
+        F: Fn(&Variable) -> Option<String>,
+    {
+        let mut out = String::new();
+
+        for part in &self.0 {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Variable(v) => {
+                    if let Some(value) = resolve(v) {
+                        out.push_str(&value);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(s: &str) -> Template {
+        Template::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn test_variable_try_from() {
+        assert_eq!(
+            Ok(Variable::RequestHeader("x-request-id".to_string())),
+            Variable::try_from("request.header.X-Request-ID")
+        );
+        assert_eq!(
+            Ok(Variable::ResponseHeader("server".to_string())),
+            Variable::try_from(" response.header.server ")
+        );
+        assert_eq!(
+            Ok(Variable::ResponseStatus),
+            Variable::try_from("response.status")
+        );
+        assert_eq!(
+            Ok(Variable::RequestPath),
+            Variable::try_from("request.path")
+        );
+        assert_eq!(
+            Ok(Variable::QueryArg("Page".to_string())),
+            Variable::try_from("request.query.Page")
+        );
+        assert_eq!(
+            Ok(Variable::Property(vec![
+                "request".to_string(),
+                "id".to_string()
+            ])),
+            Variable::try_from("request.id")
+        );
+        assert_eq!(
+            Ok(Variable::Property(vec!["route_name".to_string()])),
+            Variable::try_from("property.route_name")
+        );
+
+        for expr in [
+            "",
+            "nope",
+            "request.header.",
+            "request.query.",
+            "response.header.",
+            "property.",
+            "property.a..b",
+            "request.",
+        ] {
+            assert!(Variable::try_from(expr).is_err(), "{:?}", expr);
+        }
+    }
+
+    #[test]
+    fn test_template_try_from() {
+        assert_eq!(
+            Template(vec![Part::Literal("static".to_string())]),
+            template("static")
+        );
+        assert_eq!(Template(vec![]), template(""));
+        assert_eq!(
+            Template(vec![
+                Part::Literal("id=".to_string()),
+                Part::Variable(Variable::RequestHeader("x-id".to_string())),
+                Part::Literal(";".to_string()),
+                Part::Variable(Variable::ResponseStatus),
+            ]),
+            template("id=$(request.header.x-id);$(response.status)")
+        );
+
+        assert!(Template::try_from("$(request.header.x-id").is_err());
+        assert!(Template::try_from("$(nope)").is_err());
+    }
+
+    #[test]
+    fn test_template_escape() {
+        assert_eq!(
+            Template(vec![Part::Literal("cost $(USD)".to_string())]),
+            template("cost $$(USD)")
+        );
+        assert_eq!(
+            Template(vec![
+                Part::Literal("$$(".to_string()),
+                Part::Variable(Variable::ResponseStatus),
+                Part::Literal("$".to_string()),
+            ]),
+            template("$$$$($(response.status)$")
+        );
+        assert_eq!(
+            Template(vec![
+                Part::Literal("$".to_string()),
+                Part::Variable(Variable::ResponseHeader("x-price".to_string())),
+            ]),
+            template("$$$(response.header.x-price)")
+        );
+        assert_eq!(
+            Template(vec![Part::Literal("a$b$".to_string())]),
+            template("a$$b$")
+        );
+
+        assert_eq!(
+            Some("cost $(USD)".to_string()),
+            template("cost $$(USD)").as_literal()
+        );
+        assert_eq!(None, template("$(response.status)").as_literal());
+    }
+
+    #[test]
+    fn test_template_render() {
+        let t = template("$(request.header.a)/$(request.header.b)/$(response.status)");
+
+        let rendered = t.render(|v| match v {
+            Variable::RequestHeader(name) if name == "a" => Some("A".to_string()),
+            Variable::ResponseStatus => Some("200".to_string()),
+            _ => None,
+        });
+
+        assert_eq!("A//200", rendered);
+        assert_eq!("static", template("static").render(|_| None));
+    }
+}
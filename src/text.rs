@@ -0,0 +1,142 @@
+//! Plain-text body transformations.
+//!
+//! `body.regex_replace` rules rewrite text response bodies (e.g. HTML or text
+//! error pages), after any JSON, XML or form transformations. Other bodies,
+//! such as images, are left alone. Bodies are matched as a whole, except for
+//! NDJSON and event stream bodies, of which each line or event is matched
+//! separately as it arrives. Matching is done on bytes, so bodies need not be
+//! valid UTF-8.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+
+use log::*;
+use regex::bytes::Regex;
+
+const SEPARATOR: &str = " => ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidRegexReplace {
+    input: String,
+    reason: String,
+}
+
+impl fmt::Display for InvalidRegexReplace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid regex_replace {:?}: {}", self.input, self.reason)
+    }
+}
+
+/// A `<pattern> => <replacement>` rule. The replacement may refer to capture
+/// groups of the pattern (`$1`, `${name}`).
+#[derive(Debug, Clone)]
+pub(crate) struct RegexReplace {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl TryFrom<&str> for RegexReplace {
+    type Error = InvalidRegexReplace;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let err = |reason: String| InvalidRegexReplace {
+            input: input.to_owned(),
+            reason,
+        };
+
+        let (pattern, replacement) = input
+            .split_once(SEPARATOR)
+            .filter(|(pattern, _)| !pattern.is_empty())
+            .ok_or_else(|| err(format!("expected <pattern>{}<replacement>", SEPARATOR)))?;
+
+        Ok(RegexReplace {
+            pattern: Regex::new(pattern).map_err(|e| err(e.to_string()))?,
+            replacement: replacement.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for RegexReplace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.pattern, SEPARATOR, self.replacement)
+    }
+}
+
+/// Applies rules to a body in order, returning the new body if any of them
+/// matched.
+pub(crate) fn replace_all<'a, I>(rules: I, body: &[u8]) -> Option<Vec<u8>>
+where
+    I: IntoIterator<Item = &'a RegexReplace>,
+{
+    let mut body = Cow::Borrowed(body);
+
+    for rule in rules {
+        if let Cow::Owned(replaced) = rule.pattern.replace_all(&body, rule.replacement.as_bytes()) {
+            info!("applied regex_replace {}", rule);
+            body = Cow::Owned(replaced);
+        }
+    }
+
+    match body {
+        Cow::Owned(body) => Some(body),
+        Cow::Borrowed(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(inputs: &[&str]) -> Vec<RegexReplace> {
+        inputs
+            .iter()
+            .map(|input| RegexReplace::try_from(*input).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule = RegexReplace::try_from(r"(\w+)\.internal => $1.example.com").unwrap();
+        assert_eq!(r"(\w+)\.internal => $1.example.com", rule.to_string());
+
+        // the replacement may be empty, and may contain the separator
+        assert!(RegexReplace::try_from("a => ").is_ok());
+        assert!(RegexReplace::try_from("a => b => c").is_ok());
+
+        for input in ["a", "a=>b", " => b"] {
+            let err = RegexReplace::try_from(input).unwrap_err();
+            assert_eq!(
+                format!(
+                    "Invalid regex_replace {:?}: expected <pattern> => <replacement>",
+                    input
+                ),
+                err.to_string()
+            );
+        }
+
+        assert!(RegexReplace::try_from("( => b").is_err());
+    }
+
+    #[test]
+    fn test_replace_all() {
+        let rules = rules(&[
+            r"(?P<host>[a-z0-9-]+)\.corp\.internal => ${host}.example.com",
+            r"10\.\d+\.\d+\.\d+ => [redacted]",
+        ]);
+
+        assert_eq!(
+            Some(b"<a href=\"https://api.example.com/x\">[redacted]</a>".to_vec()),
+            replace_all(
+                &rules,
+                b"<a href=\"https://api.corp.internal/x\">10.1.2.3</a>"
+            )
+        );
+
+        assert_eq!(None, replace_all(&rules, b"nothing to see"));
+        assert_eq!(
+            Some(b"\xff[redacted]".to_vec()),
+            replace_all(&rules, b"\xff10.0.0.1")
+        );
+    }
+}
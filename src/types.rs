@@ -1,12 +1,23 @@
+use crate::content_type::ContentTypes;
+use crate::encoding::CompressedPolicy;
 use crate::json::*;
+use crate::matcher::*;
+use crate::merge::*;
+use crate::patch::*;
+use crate::path::*;
+use crate::request::RequestInfo;
+use crate::status::*;
+use crate::template::*;
+use crate::text::RegexReplace;
+use crate::xml::{Document, InvalidXmlPath, XmlPath};
 use log::*;
+use std::borrow::Cow;
 use std::convert::TryFrom;
 use std::fmt;
 
 use serde::Deserialize;
 
 use serde_json::Value as JsonValue;
-type JsonMap = serde_json::Map<String, JsonValue>;
 
 fn split_str(input: &str) -> Result<(&str, &str), InvalidKeyValue> {
     input
@@ -56,6 +67,10 @@ pub(crate) struct TransformationsConfig<T = KeyValue> {
     pub(crate) headers: Vec<T>,
     pub(crate) json: Vec<T>,
     pub(crate) json_types: Vec<Cast>,
+    pub(crate) xml: Vec<T>,
+    pub(crate) querystring: Vec<T>,
+    pub(crate) form: Vec<T>,
+    pub(crate) if_status: StatusFilter,
 }
 
 impl<T> Default for TransformationsConfig<T> {
@@ -64,30 +79,314 @@ impl<T> Default for TransformationsConfig<T> {
             headers: vec![],
             json: vec![],
             json_types: vec![],
+            xml: vec![],
+            querystring: vec![],
+            form: vec![],
+            if_status: StatusFilter::default(),
         }
     }
 }
 
+/// The kinds of name/value pairs that `Fields` transformations apply to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum FieldKind {
+    Header,
+    QueryArg,
+    FormField,
+}
+
+impl FieldKind {
+    /// The name of the config field holding entries of this kind.
+    fn key(&self) -> &'static str {
+        match self {
+            FieldKind::Header => "headers",
+            FieldKind::QueryArg => "querystring",
+            FieldKind::FormField => "form",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            FieldKind::Header => "header",
+            FieldKind::QueryArg => "query argument",
+            FieldKind::FormField => "form field",
+        }
+    }
+
+    fn eq(&self, a: &str, b: &str) -> bool {
+        match self {
+            FieldKind::Header => a.eq_ignore_ascii_case(b),
+            FieldKind::QueryArg | FieldKind::FormField => a == b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidConfig {
+    field: String,
+    reason: String,
+}
+
+impl InvalidConfig {
+    fn new<T: fmt::Display>(field: String, reason: T) -> Self {
+        InvalidConfig {
+            field,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Qualifies the field name with the name of the enclosing field.
+    fn within(mut self, parent: &str) -> Self {
+        self.field = format!("{}.{}", parent, self.field);
+        self
+    }
+}
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidConfig {}
+
+impl<T> TransformationsConfig<T> {
+    fn entries(&self, kind: FieldKind) -> &[T] {
+        match kind {
+            FieldKind::Header => &self.headers,
+            FieldKind::QueryArg => &self.querystring,
+            FieldKind::FormField => &self.form,
+        }
+    }
+
+    fn check_response(&self, section: &str) -> Result<(), InvalidConfig> {
+        if !self.querystring.is_empty() {
+            return Err(InvalidConfig::new(
+                format!("{}.{}", section, FieldKind::QueryArg.key()),
+                "only supported for request transformations",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_request(&self, section: &str) -> Result<(), InvalidConfig> {
+        if !self.if_status.is_empty() {
+            return Err(InvalidConfig::new(
+                format!("{}.if_status", section),
+                "not supported for request transformations",
+            ));
+        }
+
+        if !self.xml.is_empty() {
+            return Err(InvalidConfig::new(
+                format!("{}.xml", section),
+                "only supported for response transformations",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_json_types(&self, section: &str, allowed: bool) -> Result<(), InvalidConfig> {
+        let field = || format!("{}.json_types", section);
+
+        if !allowed && !self.json_types.is_empty() {
+            return Err(InvalidConfig::new(
+                field(),
+                "not supported for this section",
+            ));
+        }
+
+        if !self.json_types.is_empty() && self.json_types.len() != self.json.len() {
+            return Err(InvalidConfig::new(
+                field(),
+                format!(
+                    "expected {} entries (one per {}.json entry), got {}",
+                    self.json.len(),
+                    section,
+                    self.json_types.len()
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+type JsonValues = Vec<(JsonPath, JsonValue)>;
+
 impl TransformationsConfig {
-    fn cast_json(mut self) -> Vec<(String, JsonValue)> {
+    fn cast_json(
+        mut self,
+        section: JsonSection,
+        parse_path: fn(&str) -> Result<JsonPath, InvalidJsonPath>,
+    ) -> Result<(JsonValues, Vec<JsonTemplate>), InvalidConfig> {
+        let name = section.name();
+        self.check_json_types(name, true)?;
+
         let mut json_values = Vec::with_capacity(self.json.len());
+        let mut templates = vec![];
         let json = self.json.drain(..);
 
         for (i, kv) in json.enumerate() {
-            if let Some(typ) = self.json_types.get(i) {
-                json_values.push((kv.0, typ.convert(kv.1)));
-            } else {
-                json_values.push((kv.0, Cast::String.convert(kv.1)));
-            }
+            let field = || format!("{}.json[{}]", name, i);
+
+            let path = parse_path(&kv.0).map_err(|e| InvalidConfig::new(field(), e))?;
+            let typ = self.json_types.get(i).unwrap_or(&Cast::String);
+
+            let template =
+                Template::try_from(kv.1.as_str()).map_err(|e| InvalidConfig::new(field(), e))?;
+
+            // templated values can only be cast once they have been rendered
+            let value = match template.as_literal() {
+                Some(literal) => typ
+                    .convert(literal)
+                    .map_err(|e| InvalidConfig::new(field(), e))?,
+                None => {
+                    templates.push(JsonTemplate {
+                        section,
+                        index: i,
+                        template,
+                        cast: typ.clone(),
+                    });
+
+                    JsonValue::Null
+                }
+            };
+
+            json_values.push((path, value));
         }
 
-        json_values
+        Ok((json_values, templates))
+    }
+
+    fn templates(
+        &self,
+        kind: FieldKind,
+        section: &str,
+    ) -> Result<Vec<(String, Template)>, InvalidConfig> {
+        self.entries(kind)
+            .iter()
+            .enumerate()
+            .map(|(i, KeyValue(name, value))| {
+                Template::try_from(value.as_str())
+                    .map(|t| (name.clone(), t))
+                    .map_err(|e| {
+                        InvalidConfig::new(format!("{}.{}[{}]", section, kind.key(), i), e)
+                    })
+            })
+            .collect()
+    }
+
+    fn json_paths(&self, section: &str) -> Result<Vec<(JsonPath, JsonPath)>, InvalidConfig> {
+        self.check_json_types(section, false)?;
+
+        self.json
+            .iter()
+            .enumerate()
+            .map(|(i, KeyValue(from, to))| {
+                JsonPath::rename_pair(from, to)
+                    .map_err(|e| InvalidConfig::new(format!("{}.json[{}]", section, i), e))
+            })
+            .collect()
+    }
+
+    fn xml_paths(&self, section: &str) -> Result<Vec<(XmlPath, String)>, InvalidConfig> {
+        self.xml
+            .iter()
+            .enumerate()
+            .map(|(i, KeyValue(from, to))| {
+                XmlPath::rename_pair(from, to)
+                    .map_err(|e| InvalidConfig::new(format!("{}.xml[{}]", section, i), e))
+            })
+            .collect()
+    }
+
+    fn xml_values(
+        &self,
+        section: &str,
+        parse_path: fn(&str) -> Result<XmlPath, InvalidXmlPath>,
+    ) -> Result<Vec<(XmlPath, Template)>, InvalidConfig> {
+        self.xml
+            .iter()
+            .enumerate()
+            .map(|(i, KeyValue(path, value))| {
+                let field = || format!("{}.xml[{}]", section, i);
+                let path = parse_path(path).map_err(|e| InvalidConfig::new(field(), e))?;
+                let template = Template::try_from(value.as_str())
+                    .map_err(|e| InvalidConfig::new(field(), e))?;
+
+                Ok((path, template))
+            })
+            .collect()
+    }
+}
+
+impl TransformationsConfig<String> {
+    fn json_paths(&self, section: &str) -> Result<Vec<JsonPath>, InvalidConfig> {
+        self.check_json_types(section, false)?;
+
+        self.json
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                JsonPath::try_from(s.as_str())
+                    .map_err(|e| InvalidConfig::new(format!("{}.json[{}]", section, i), e))
+            })
+            .collect()
+    }
+
+    fn xml_paths(&self, section: &str) -> Result<Vec<XmlPath>, InvalidConfig> {
+        self.xml
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                XmlPath::child(s)
+                    .map_err(|e| InvalidConfig::new(format!("{}.xml[{}]", section, i), e))
+            })
+            .collect()
     }
 }
 
+/// How JSON transformations are applied to a response body that is an array.
+#[derive(Deserialize, Default, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ArrayMode {
+    /// Apply transformations to each object element of the array.
+    #[default]
+    Elements,
+    /// Apply transformations to the array itself, so paths must begin with
+    /// an array index (e.g. `[*].id`).
+    Root,
+    /// Leave array bodies untouched, apart from `merge` and `patch`, which
+    /// always apply to the whole body.
+    Skip,
+}
+
+/// What happens to a request or response body that is larger than
+/// `max_body_size`.
+#[derive(Deserialize, Default, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum OversizePolicy {
+    /// Forward the body without transforming it.
+    #[default]
+    Passthrough,
+    /// Reject the request with a 413, or replace the response with a 502. If
+    /// the body is only found to be too large after the headers have been
+    /// forwarded, the stream is reset instead.
+    Reject,
+    /// Forward the headers and drop the body, removing any `content-encoding`
+    /// header. If the body is only found to be too large after the headers
+    /// have been forwarded, the header can no longer be removed.
+    TruncateHeadersOnly,
+}
+
+/// The remove/rename/replace/add/append sections of a set of
+/// transformations.
 #[derive(Deserialize, Default, PartialEq, Eq, Debug, Clone)]
 #[serde(default)]
-pub(crate) struct ConfigInput {
+pub(crate) struct SectionsInput {
     remove: TransformationsConfig<String>,
     rename: TransformationsConfig,
     replace: TransformationsConfig,
@@ -95,382 +394,2291 @@ pub(crate) struct ConfigInput {
     append: TransformationsConfig,
 }
 
-impl From<ConfigInput> for Config {
-    fn from(val: ConfigInput) -> Self {
-        let mut config: Config = Default::default();
+impl SectionsInput {
+    fn status(&self) -> SectionStatus {
+        SectionStatus {
+            remove: self.remove.if_status.clone(),
+            rename: self.rename.if_status.clone(),
+            replace: self.replace.if_status.clone(),
+            add: self.add.if_status.clone(),
+            append: self.append.if_status.clone(),
+        }
+    }
+
+    fn check_response(&self) -> Result<(), InvalidConfig> {
+        self.remove.check_response("remove")?;
+        self.rename.check_response("rename")?;
+        self.replace.check_response("replace")?;
+        self.add.check_response("add")?;
+        self.append.check_response("append")
+    }
+
+    fn check_request(&self) -> Result<(), InvalidConfig> {
+        self.remove.check_request("remove")?;
+        self.rename.check_request("rename")?;
+        self.replace.check_request("replace")?;
+        self.add.check_request("add")?;
+        self.append.check_request("append")
+    }
 
-        if !val.remove.headers.is_empty()
-            || !val.rename.headers.is_empty()
-            || !val.replace.headers.is_empty()
-            || !val.add.headers.is_empty()
-            || !val.append.headers.is_empty()
+    fn fields(&self, kind: FieldKind) -> Result<Option<Fields>, InvalidConfig> {
+        if self.remove.entries(kind).is_empty()
+            && self.rename.entries(kind).is_empty()
+            && self.replace.entries(kind).is_empty()
+            && self.add.entries(kind).is_empty()
+            && self.append.entries(kind).is_empty()
         {
-            config.headers = Some(Headers {
-                remove: val.remove.headers,
-                rename: val.rename.headers,
-                replace: val.replace.headers.clone(),
-                add: val.add.headers.clone(),
-                append: val.append.headers.clone(),
-            });
+            return Ok(None);
         }
 
-        if !val.remove.json.is_empty()
-            || !val.rename.json.is_empty()
-            || !val.replace.json.is_empty()
-            || !val.add.json.is_empty()
-            || !val.append.json.is_empty()
+        Ok(Some(Fields {
+            remove: self.remove.entries(kind).to_vec(),
+            rename: self.rename.entries(kind).to_vec(),
+            replace: self.replace.templates(kind, "replace")?,
+            add: self.add.templates(kind, "add")?,
+            append: self.append.templates(kind, "append")?,
+            if_status: self.status(),
+        }))
+    }
+
+    fn json(
+        self,
+        merge: Option<JsonMap>,
+        patch: Vec<PatchOperation>,
+        array_mode: ArrayMode,
+    ) -> Result<Option<Json>, InvalidConfig> {
+        if self.remove.json.is_empty()
+            && self.rename.json.is_empty()
+            && self.replace.json.is_empty()
+            && self.add.json.is_empty()
+            && self.append.json.is_empty()
+            && merge.is_none()
+            && patch.is_empty()
         {
-            config.json = Some(Json {
-                remove: val.remove.json,
-                rename: val.rename.json,
-                replace: val.replace.cast_json(),
-                add: val.add.cast_json(),
-                append: val.append.cast_json(),
-            });
+            return Ok(None);
+        }
+
+        let if_status = self.status();
+
+        let (replace, mut templates) = self
+            .replace
+            .cast_json(JsonSection::Replace, |s| JsonPath::try_from(s))?;
+        let (add, add_templates) = self.add.cast_json(JsonSection::Add, JsonPath::member)?;
+        let (append, append_templates) = self
+            .append
+            .cast_json(JsonSection::Append, JsonPath::member)?;
+
+        templates.extend(add_templates);
+        templates.extend(append_templates);
+
+        Ok(Some(Json {
+            remove: self.remove.json_paths("remove")?,
+            rename: self.rename.json_paths("rename")?,
+            replace,
+            add,
+            append,
+            merge: merge.map(JsonValue::Object),
+            patch,
+            array_mode,
+            templates,
+            if_status,
+        }))
+    }
+
+    fn xml(&self) -> Result<Option<Xml>, InvalidConfig> {
+        if self.remove.xml.is_empty()
+            && self.rename.xml.is_empty()
+            && self.replace.xml.is_empty()
+            && self.add.xml.is_empty()
+            && self.append.xml.is_empty()
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(Xml {
+            remove: self.remove.xml_paths("remove")?,
+            rename: self.rename.xml_paths("rename")?,
+            replace: self
+                .replace
+                .xml_values("replace", |s| XmlPath::try_from(s))?,
+            add: self.add.xml_values("add", XmlPath::named)?,
+            append: self.append.xml_values("append", XmlPath::named_element)?,
+            if_status: self.status(),
+        }))
+    }
+}
+
+impl SectionsInput {
+    fn into_request(self) -> Result<Option<RequestTransformations>, InvalidConfig> {
+        self.check_request()?;
+
+        let request = RequestTransformations {
+            headers: self.fields(FieldKind::Header)?,
+            querystring: self.fields(FieldKind::QueryArg)?,
+            form: self.fields(FieldKind::FormField)?,
+            json: self.json(None, vec![], ArrayMode::default())?,
+        };
+
+        let is_response =
+            |var: &Variable| matches!(var, Variable::ResponseHeader(_) | Variable::ResponseStatus);
+        let err = |field: String| {
+            InvalidConfig::new(
+                field,
+                "response values are not available to request transformations",
+            )
+        };
+
+        for (kind, fields) in [
+            (FieldKind::Header, &request.headers),
+            (FieldKind::QueryArg, &request.querystring),
+            (FieldKind::FormField, &request.form),
+        ] {
+            if let Some((section, i)) = fields.as_ref().and_then(|f| f.find_template(is_response)) {
+                return Err(err(format!("{}.{}[{}]", section, kind.key(), i)));
+            }
+        }
+
+        if let Some(t) = request
+            .json
+            .iter()
+            .flat_map(|json| &json.templates)
+            .find(|t| t.template.variables().any(is_response))
+        {
+            return Err(err(format!("{}.json[{}]", t.section.name(), t.index)));
+        }
+
+        if request.headers.is_none()
+            && request.querystring.is_none()
+            && request.form.is_none()
+            && request.json.is_none()
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(request))
+    }
+}
+
+#[derive(Deserialize, Default, PartialEq, Eq, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct RuleInput {
+    #[serde(rename = "match")]
+    matcher: MatchInput,
+    remove: TransformationsConfig<String>,
+    rename: TransformationsConfig,
+    replace: TransformationsConfig,
+    add: TransformationsConfig,
+    append: TransformationsConfig,
+    merge: Option<JsonMap>,
+    patch: Vec<PatchOperation>,
+    array_mode: ArrayMode,
+    if_status: StatusFilter,
+    request: SectionsInput,
+    body: BodyInput,
+}
+
+/// Transformations of response bodies as plain text.
+#[derive(Deserialize, Default, PartialEq, Eq, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct BodyInput {
+    regex_replace: Vec<String>,
+}
+
+impl BodyInput {
+    fn regex_replace(&self) -> Result<Vec<RegexReplace>, InvalidConfig> {
+        self.regex_replace
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                RegexReplace::try_from(input.as_str())
+                    .map_err(|e| InvalidConfig::new(format!("body.regex_replace[{}]", i), e))
+            })
+            .collect()
+    }
+}
+
+/// The top-level transformations form the default rule, which is applied
+/// before any of the rules in `rules`.
+#[derive(Deserialize, Default, PartialEq, Eq, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct ConfigInput {
+    #[serde(flatten)]
+    default: RuleInput,
+    rules: Vec<RuleInput>,
+    max_body_size: Option<usize>,
+    on_oversize: OversizePolicy,
+    on_compressed: CompressedPolicy,
+    content_types: Vec<String>,
+    json_without_content_type: bool,
+    sniff_json: bool,
+}
+
+impl TryFrom<RuleInput> for Rule {
+    type Error = InvalidConfig;
+
+    fn try_from(val: RuleInput) -> Result<Self, Self::Error> {
+        let matcher =
+            Match::try_from(val.matcher).map_err(|e| InvalidConfig::new("match".to_owned(), e))?;
+
+        let sections = SectionsInput {
+            remove: val.remove,
+            rename: val.rename,
+            replace: val.replace,
+            add: val.add,
+            append: val.append,
+        };
+
+        sections.check_response()?;
+
+        let request = val
+            .request
+            .into_request()
+            .map_err(|e| e.within("request"))?;
+
+        if request.is_some() && (!val.if_status.is_empty() || matcher.has_response_conditions()) {
+            return Err(InvalidConfig::new(
+                "request".to_owned(),
+                "request transformations cannot be combined with response conditions \
+                 (if_status, match.content_type)",
+            ));
+        }
+
+        let xml = sections.xml()?;
+        let regex_replace = val.body.regex_replace()?;
+
+        Ok(Rule {
+            matcher,
+            headers: sections.fields(FieldKind::Header)?,
+            form: sections.fields(FieldKind::FormField)?,
+            json: sections.json(val.merge, val.patch, val.array_mode)?,
+            xml,
+            regex_replace,
+            request,
+            if_status: val.if_status,
+        })
+    }
+}
+
+impl TryFrom<ConfigInput> for Config {
+    type Error = InvalidConfig;
+
+    fn try_from(val: ConfigInput) -> Result<Self, Self::Error> {
+        let mut rules = vec![];
+
+        // top-level conditions only apply to the top-level transformations
+        let default = Rule::try_from(val.default)?;
+        if default.has_transformations() {
+            rules.push(default);
+        } else if !default.if_status.is_empty() {
+            return Err(InvalidConfig::new(
+                "if_status".to_owned(),
+                "there are no top-level transformations to apply it to, set it on each of rules instead",
+            ));
+        } else if !default.matcher.is_empty() {
+            return Err(InvalidConfig::new(
+                "match".to_owned(),
+                "there are no top-level transformations to apply it to, set it on each of rules instead",
+            ));
+        }
+
+        for (i, rule) in val.rules.into_iter().enumerate() {
+            rules.push(Rule::try_from(rule).map_err(|e| e.within(&format!("rules[{}]", i)))?);
+        }
+
+        let mut content_types = ContentTypes::try_from(val.content_types.as_slice())
+            .map_err(|e| InvalidConfig::new("content_types".to_owned(), e))?;
+        content_types.missing = val.json_without_content_type;
+        content_types.sniff = val.sniff_json;
+
+        let mut request_headers = vec![];
+        for name in rules.iter().flat_map(Rule::request_headers) {
+            if !request_headers.contains(name) {
+                request_headers.push(name.clone());
+            }
         }
 
-        config
+        Ok(Config {
+            rules,
+            request_headers,
+            max_body_size: val.max_body_size,
+            on_oversize: val.on_oversize,
+            on_compressed: val.on_compressed,
+            content_types,
+        })
     }
 }
 
+/// Transformations of a list of name/value pairs: headers, query string
+/// arguments or form fields.
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub(crate) struct Headers {
+pub(crate) struct Fields {
     pub(crate) remove: Vec<String>,
     pub(crate) rename: Vec<KeyValue>,
-    pub(crate) replace: Vec<KeyValue>,
-    pub(crate) add: Vec<KeyValue>,
-    pub(crate) append: Vec<KeyValue>,
+    pub(crate) replace: Vec<(String, Template)>,
+    pub(crate) add: Vec<(String, Template)>,
+    pub(crate) append: Vec<(String, Template)>,
+    pub(crate) if_status: SectionStatus,
+}
+
+impl StatusSections for Fields {
+    fn if_status(&self) -> &SectionStatus {
+        &self.if_status
+    }
+
+    fn clear_section(&mut self, section: Section) {
+        match section {
+            Section::Remove => self.remove.clear(),
+            Section::Rename => self.rename.clear(),
+            Section::Replace => self.replace.clear(),
+            Section::Add => self.add.clear(),
+            Section::Append => self.append.clear(),
+        }
+    }
+}
+
+impl Fields {
+    fn variables(&self) -> impl Iterator<Item = &Variable> {
+        self.replace
+            .iter()
+            .chain(&self.add)
+            .chain(&self.append)
+            .flat_map(|(_, template)| template.variables())
+    }
+
+    /// Returns the section and index of the first templated value that refers
+    /// to a variable matching `predicate`.
+    fn find_template<P>(&self, predicate: P) -> Option<(&'static str, usize)>
+    where
+        P: Fn(&Variable) -> bool,
+    {
+        [
+            ("replace", &self.replace),
+            ("add", &self.add),
+            ("append", &self.append),
+        ]
+        .into_iter()
+        .find_map(|(section, values)| {
+            values
+                .iter()
+                .position(|(_, template)| template.variables().any(&predicate))
+                .map(|i| (section, i))
+        })
+    }
+
+    /// Transforms a list of name/value pairs, using `render` to evaluate
+    /// templated values. Returns whether the list was changed.
+    pub(crate) fn transform<F>(
+        &self,
+        kind: FieldKind,
+        fields: &mut Vec<(String, String)>,
+        render: F,
+    ) -> bool
+    where
+        F: Fn(&Template) -> String,
+    {
+        // https://docs.konghq.com/hub/kong-inc/response-transformer/#order-of-execution
+
+        let kind_name = kind.name();
+        let has = |fields: &[(String, String)], name: &str| {
+            fields.iter().any(|(found, _)| kind.eq(found, name))
+        };
+
+        let mut changed = false;
+
+        for name in &self.remove {
+            if has(fields, name) {
+                info!("removing {}: {}", kind_name, name);
+                fields.retain(|(found, _)| !kind.eq(found, name));
+                changed = true;
+            }
+        }
+
+        for KeyValue(from, to) in &self.rename {
+            if has(fields, from) {
+                info!("renaming {} {} => {}", kind_name, from, to);
+                fields.retain(|(found, _)| kind.eq(found, from) || !kind.eq(found, to));
+                fields
+                    .iter_mut()
+                    .filter(|(found, _)| kind.eq(found, from))
+                    .for_each(|(found, _)| *found = to.clone());
+                changed = true;
+            }
+        }
+
+        for (name, template) in &self.replace {
+            if has(fields, name) {
+                let value = render(template);
+                info!("updating {} {} value to {}", kind_name, name, value);
+
+                // replace the first value and drop the rest
+                let mut replaced = false;
+                fields.retain_mut(|(found, found_value)| {
+                    if !kind.eq(found, name) {
+                        return true;
+                    }
+
+                    if replaced {
+                        return false;
+                    }
+
+                    replaced = true;
+                    *found_value = value.clone();
+                    true
+                });
+                changed = true;
+            }
+        }
+
+        for (name, template) in &self.add {
+            if !has(fields, name) {
+                let value = render(template);
+                info!("adding {} {} => {}", kind_name, name, value);
+                fields.push((name.clone(), value));
+                changed = true;
+            }
+        }
+
+        for (name, template) in &self.append {
+            let value = render(template);
+            info!("appending {} {} => {}", kind_name, name, value);
+            fields.push((name.clone(), value));
+            changed = true;
+        }
+
+        changed
+    }
+}
+
+/// Status filters for each of the remove/rename/replace/add/append sections.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub(crate) struct SectionStatus {
+    remove: StatusFilter,
+    rename: StatusFilter,
+    replace: StatusFilter,
+    add: StatusFilter,
+    append: StatusFilter,
+}
+
+impl SectionStatus {
+    fn get(&self, section: Section) -> &StatusFilter {
+        match section {
+            Section::Remove => &self.remove,
+            Section::Rename => &self.rename,
+            Section::Replace => &self.replace,
+            Section::Add => &self.add,
+            Section::Append => &self.append,
+        }
+    }
+
+    fn matches_all(&self, status: Option<u16>) -> bool {
+        Section::ALL
+            .iter()
+            .all(|section| self.get(*section).matches(status))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Section {
+    Remove,
+    Rename,
+    Replace,
+    Add,
+    Append,
+}
+
+impl Section {
+    const ALL: [Section; 5] = [
+        Section::Remove,
+        Section::Rename,
+        Section::Replace,
+        Section::Add,
+        Section::Append,
+    ];
+}
+
+/// Transformations made of remove/rename/replace/add/append sections, each of
+/// which may only apply to some response statuses.
+pub(crate) trait StatusSections: Clone {
+    fn if_status(&self) -> &SectionStatus;
+
+    /// Drops the transformations of a section.
+    fn clear_section(&mut self, section: Section);
+
+    /// Returns the transformations that apply to a response with the given
+    /// status.
+    fn for_status(&self, status: Option<u16>) -> Cow<'_, Self> {
+        let s = self.if_status();
+
+        if s.matches_all(status) {
+            return Cow::Borrowed(self);
+        }
+
+        let mut filtered = self.clone();
+
+        for section in Section::ALL {
+            if !s.get(section).matches(status) {
+                filtered.clear_section(section);
+            }
+        }
+
+        Cow::Owned(filtered)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum JsonSection {
+    Replace,
+    Add,
+    Append,
+}
+
+impl From<JsonSection> for Section {
+    fn from(section: JsonSection) -> Self {
+        match section {
+            JsonSection::Replace => Section::Replace,
+            JsonSection::Add => Section::Add,
+            JsonSection::Append => Section::Append,
+        }
+    }
+}
+
+impl JsonSection {
+    fn name(&self) -> &'static str {
+        match self {
+            JsonSection::Replace => "replace",
+            JsonSection::Add => "add",
+            JsonSection::Append => "append",
+        }
+    }
+}
+
+/// A JSON value that must be rendered for each request.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct JsonTemplate {
+    section: JsonSection,
+    index: usize,
+    template: Template,
+    cast: Cast,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub(crate) struct Json {
-    pub(crate) remove: Vec<String>,
-    pub(crate) rename: Vec<KeyValue>,
-    pub(crate) replace: Vec<(String, JsonValue)>,
-    pub(crate) add: Vec<(String, JsonValue)>,
-    pub(crate) append: Vec<(String, JsonValue)>,
+    pub(crate) remove: Vec<JsonPath>,
+    pub(crate) rename: Vec<(JsonPath, JsonPath)>,
+    pub(crate) replace: Vec<(JsonPath, JsonValue)>,
+    pub(crate) add: Vec<(JsonPath, JsonValue)>,
+    pub(crate) append: Vec<(JsonPath, JsonValue)>,
+    pub(crate) merge: Option<JsonValue>,
+    pub(crate) patch: Vec<PatchOperation>,
+    pub(crate) array_mode: ArrayMode,
+    pub(crate) templates: Vec<JsonTemplate>,
+    pub(crate) if_status: SectionStatus,
+}
+
+impl StatusSections for Json {
+    fn if_status(&self) -> &SectionStatus {
+        &self.if_status
+    }
+
+    fn clear_section(&mut self, section: Section) {
+        match section {
+            Section::Remove => self.remove.clear(),
+            Section::Rename => self.rename.clear(),
+            Section::Replace => self.replace.clear(),
+            Section::Add => self.add.clear(),
+            Section::Append => self.append.clear(),
+        }
+
+        self.templates
+            .retain(|t| Section::from(t.section) != section);
+    }
 }
 
-impl Json {
-    pub(crate) fn transform_body(&self, body: &mut JsonMap) -> bool {
-        // https://docs.konghq.com/hub/kong-inc/response-transformer/#order-of-execution
+impl Json {
+    fn variables(&self) -> impl Iterator<Item = &Variable> {
+        self.templates.iter().flat_map(|t| t.template.variables())
+    }
+
+    /// Returns the transformations with all templated values rendered.
+    pub(crate) fn resolve<F>(&self, render: F) -> Cow<'_, Json>
+    where
+        F: Fn(&Template) -> String,
+    {
+        if self.templates.is_empty() {
+            return Cow::Borrowed(self);
+        }
+
+        let mut resolved = self.clone();
+
+        for t in &self.templates {
+            let rendered = render(&t.template);
+
+            let value = match t.cast.convert(rendered.clone()) {
+                Ok(value) => value,
+                Err(e) => {
+                    warn!("{} (from template), using string value instead", e);
+                    JsonValue::String(rendered)
+                }
+            };
+
+            let values = match t.section {
+                JsonSection::Replace => &mut resolved.replace,
+                JsonSection::Add => &mut resolved.add,
+                JsonSection::Append => &mut resolved.append,
+            };
+
+            values[t.index].1 = value;
+        }
+
+        Cow::Owned(resolved)
+    }
+
+    /// Returns member names of which at least one must be present in a body
+    /// for these transformations to change it, or `None` if any body could be
+    /// changed.
+    pub(crate) fn required_keys(&self) -> Option<Vec<&str>> {
+        if !self.add.is_empty()
+            || !self.append.is_empty()
+            || self.merge.is_some()
+            || !self.patch.is_empty()
+        {
+            return None;
+        }
+
+        let paths = self.remove.iter();
+        let paths = paths.chain(self.rename.iter().map(|(from, _)| from));
+        let paths = paths.chain(self.replace.iter().map(|(path, _)| path));
+
+        paths.map(JsonPath::last_key).collect()
+    }
+
+    /// Transforms a JSON body, which may be an object or an array.
+    pub(crate) fn transform(&self, body: &mut JsonValue) -> bool {
+        if body.is_array() {
+            // `merge` and `patch` address the body as a whole, whatever the
+            // array mode
+            self.transform_array(body) | self.transform_document(body)
+        } else {
+            self.transform_body(body)
+        }
+    }
+
+    /// Applies the field operations to a response body that is a JSON
+    /// array.
+    pub(crate) fn transform_array(&self, body: &mut JsonValue) -> bool {
+        match (self.array_mode, body) {
+            (ArrayMode::Root, body) => self.transform_fields(body),
+            (ArrayMode::Elements, JsonValue::Array(items)) => {
+                let mut changed = false;
+
+                for item in items.iter_mut().filter(|item| item.is_object()) {
+                    changed |= self.transform_fields(item);
+                }
+
+                changed
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn transform_body(&self, body: &mut JsonValue) -> bool {
+        self.transform_fields(body) | self.transform_document(body)
+    }
+
+    /// Applies the remove/rename/replace/add/append operations.
+    fn transform_fields(&self, body: &mut JsonValue) -> bool {
+        // https://docs.konghq.com/hub/kong-inc/response-transformer/#order-of-execution
+
+        let mut changed = false;
+
+        for field in &self.remove {
+            if !field.remove(body).is_empty() {
+                info!("removed field {}", field);
+                changed = true;
+            }
+        }
+
+        for (from, to) in &self.rename {
+            if from.rename(body, to) > 0 {
+                info!("renamed {} => {}", from, to);
+                changed = true;
+            }
+        }
+
+        for (field, value) in &self.replace {
+            field.for_each_mut(body, |found| {
+                if found != value {
+                    info!("replacing field {} {:?} => {:?}", field, found, value);
+                    *found = value.clone();
+                    changed = true;
+                }
+            });
+        }
+
+        for (field, value) in &self.add {
+            field.for_each_member(body, true, |map, key| {
+                if !map.contains_key(key) {
+                    info!("adding field {} {:?}", field, value);
+                    map.insert(key.to_owned(), value.clone());
+                    changed = true;
+                }
+            });
+        }
+
+        for (field, value) in &self.append {
+            field.for_each_member(body, true, |map, key| {
+                map.entry(key)
+                    .and_modify(|found| {
+                        let current = found.take();
+                        let mut appended = false;
+
+                        *found = match current {
+                            JsonValue::String(_) => {
+                                appended = true;
+                                serde_json::json!([current, value.clone()])
+                            }
+                            JsonValue::Array(mut arr) => {
+                                appended = true;
+                                arr.push(value.clone());
+                                arr.into()
+                            }
+                            // XXX: this branch is not fully compatible with the Lua plugin
+                            //
+                            // The lua plugin doesn't attempt to disambiguate between an
+                            // array-like table and a map-like table. It just blindly calls
+                            // the `table.insert()` function.
+                            _ => current,
+                        };
+
+                        if appended {
+                            changed = true;
+                            info!("appended {:?} to {}", value, field);
+                        }
+                    })
+                    .or_insert_with(|| {
+                        changed = true;
+                        let new = serde_json::json!([value]);
+                        info!("inserted {:?} to {}", new, field);
+                        new
+                    });
+            });
+        }
+
+        changed
+    }
+
+    /// Applies `merge` and then `patch`, which address the whole body.
+    fn transform_document(&self, body: &mut JsonValue) -> bool {
+        let mut changed = false;
+
+        if let Some(merge) = &self.merge {
+            if merge_patch(body, merge) {
+                info!("applied JSON merge patch");
+                changed = true;
+            }
+        }
+
+        if !self.patch.is_empty() {
+            match apply_patch(&self.patch, body) {
+                Ok(patched) => {
+                    if patched {
+                        info!("applied JSON patch");
+                        changed = true;
+                    }
+                }
+                Err(e) => warn!("failed to apply JSON patch ({}), skipping", e),
+            }
+        }
+
+        changed
+    }
+}
+
+/// Transformations of an XML body. Values may be templates, which are
+/// rendered for each response.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct Xml {
+    pub(crate) remove: Vec<XmlPath>,
+    pub(crate) rename: Vec<(XmlPath, String)>,
+    pub(crate) replace: Vec<(XmlPath, Template)>,
+    pub(crate) add: Vec<(XmlPath, Template)>,
+    pub(crate) append: Vec<(XmlPath, Template)>,
+    pub(crate) if_status: SectionStatus,
+}
+
+impl StatusSections for Xml {
+    fn if_status(&self) -> &SectionStatus {
+        &self.if_status
+    }
+
+    fn clear_section(&mut self, section: Section) {
+        match section {
+            Section::Remove => self.remove.clear(),
+            Section::Rename => self.rename.clear(),
+            Section::Replace => self.replace.clear(),
+            Section::Add => self.add.clear(),
+            Section::Append => self.append.clear(),
+        }
+    }
+}
+
+impl Xml {
+    fn variables(&self) -> impl Iterator<Item = &Variable> {
+        self.replace
+            .iter()
+            .chain(&self.add)
+            .chain(&self.append)
+            .flat_map(|(_, template)| template.variables())
+    }
+
+    /// Transforms an XML body, using `render` to evaluate templated values.
+    /// Returns whether the body was changed.
+    pub(crate) fn transform<F>(&self, doc: &mut Document, render: F) -> bool
+    where
+        F: Fn(&Template) -> String,
+    {
+        // https://docs.konghq.com/hub/kong-inc/response-transformer/#order-of-execution
+
+        let mut changed = false;
+
+        for path in &self.remove {
+            if doc.remove(path) > 0 {
+                info!("removed XML {}", path);
+                changed = true;
+            }
+        }
+
+        for (path, to) in &self.rename {
+            if doc.rename(path, to) > 0 {
+                info!("renamed XML {} => {}", path, to);
+                changed = true;
+            }
+        }
+
+        for (path, template) in &self.replace {
+            let value = render(template);
+            if doc.replace(path, &value) > 0 {
+                info!("replacing XML {} => {:?}", path, value);
+                changed = true;
+            }
+        }
+
+        for (path, template) in &self.add {
+            let value = render(template);
+            if doc.add(path, &value) > 0 {
+                info!("adding XML {} {:?}", path, value);
+                changed = true;
+            }
+        }
+
+        for (path, template) in &self.append {
+            let value = render(template);
+            if doc.append(path, &value) > 0 {
+                info!("appending XML {} {:?}", path, value);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+/// A set of transformations, applied to the requests and responses that
+/// match its conditions.
+#[derive(Debug, Clone)]
+pub(crate) struct Rule {
+    pub(crate) matcher: Match,
+    pub(crate) headers: Option<Fields>,
+    pub(crate) form: Option<Fields>,
+    pub(crate) json: Option<Json>,
+    pub(crate) xml: Option<Xml>,
+    /// Applied to text response bodies, after the other body
+    /// transformations.
+    pub(crate) regex_replace: Vec<RegexReplace>,
+    pub(crate) request: Option<RequestTransformations>,
+    pub(crate) if_status: StatusFilter,
+}
+
+impl Rule {
+    fn has_transformations(&self) -> bool {
+        self.headers.is_some()
+            || self.form.is_some()
+            || self.json.is_some()
+            || self.xml.is_some()
+            || !self.regex_replace.is_empty()
+            || self.request.is_some()
+    }
+
+    /// Returns whether the rule applies to a request and its response.
+    pub(crate) fn matches(
+        &self,
+        request: &RequestInfo,
+        status: Option<u16>,
+        content_type: Option<&str>,
+    ) -> bool {
+        self.if_status.matches(status) && self.matcher.matches(request, content_type)
+    }
+
+    /// Returns whether the rule's request transformations apply to a
+    /// request.
+    pub(crate) fn matches_request(&self, request: &RequestInfo) -> bool {
+        self.request.is_some() && self.matcher.matches_request(request)
+    }
+
+    fn request_headers(&self) -> impl Iterator<Item = &String> {
+        let variables = self
+            .headers
+            .iter()
+            .chain(&self.form)
+            .flat_map(Fields::variables)
+            .chain(self.json.iter().flat_map(Json::variables))
+            .chain(self.xml.iter().flat_map(Xml::variables))
+            .chain(
+                self.request
+                    .iter()
+                    .flat_map(RequestTransformations::variables),
+            );
+
+        self.matcher
+            .request_headers()
+            .chain(variables.filter_map(|var| match var {
+                Variable::RequestHeader(name) => Some(name),
+                _ => None,
+            }))
+    }
+}
+
+/// Transformations applied to the request before it is forwarded upstream.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct RequestTransformations {
+    pub(crate) headers: Option<Fields>,
+    pub(crate) querystring: Option<Fields>,
+    pub(crate) form: Option<Fields>,
+    pub(crate) json: Option<Json>,
+}
+
+impl RequestTransformations {
+    fn variables(&self) -> impl Iterator<Item = &Variable> {
+        self.headers
+            .iter()
+            .chain(&self.querystring)
+            .chain(&self.form)
+            .flat_map(Fields::variables)
+            .chain(self.json.iter().flat_map(Json::variables))
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub(crate) struct Config {
+    /// Rules, in the order they are applied.
+    pub(crate) rules: Vec<Rule>,
+    /// Request headers that must be captured for rule conditions and
+    /// templates.
+    pub(crate) request_headers: Vec<String>,
+    /// The largest request or response body, in bytes, that is buffered for
+    /// transformations. Streamed bodies are limited in what is held back
+    /// instead: a single NDJSON line or event, or the members of a JSON object
+    /// that wait for a rename.
+    pub(crate) max_body_size: Option<usize>,
+    pub(crate) on_oversize: OversizePolicy,
+    pub(crate) on_compressed: CompressedPolicy,
+    /// The content types of bodies that are transformed as JSON.
+    pub(crate) content_types: ContentTypes,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn path(s: &str) -> JsonPath {
+        JsonPath::try_from(s).unwrap()
+    }
+
+    impl KeyValue {
+        #[warn(unused)]
+        pub(crate) fn new<T: std::string::ToString>(name: T, value: T) -> Self {
+            KeyValue(name.to_string(), value.to_string())
+        }
+    }
+
+    #[test]
+    fn test_header_try_from_valid() {
+        assert_eq!(Ok(KeyValue::new("a", "b")), KeyValue::try_from("a:b"));
+    }
+
+    #[test]
+    fn test_header_try_from_invalid() {
+        assert_eq!(
+            Err(InvalidKeyValue("a".to_string())),
+            KeyValue::try_from("a")
+        );
+        assert_eq!(
+            Err(InvalidKeyValue("a:".to_string())),
+            KeyValue::try_from("a:")
+        );
+        assert_eq!(
+            Err(InvalidKeyValue(":b".to_string())),
+            KeyValue::try_from(":b")
+        );
+    }
+
+    #[test]
+    fn test_json_deserialize_transformations() {
+        assert_eq!(
+            TransformationsConfig {
+                headers: vec![KeyValue::new("a", "b"), KeyValue::new("c", "d")],
+                ..Default::default()
+            },
+            serde_json::from_str(r#"{ "headers": ["a:b", "c:d"] }"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_transform_remove() {
+        let tx = Json {
+            remove: vec![path("remove_me")],
+            ..Default::default()
+        };
+
+        let mut body = json!({
+            "remove_me": "goodbye",
+            "unchanged": true
+        });
+
+        assert!(tx.transform_body(&mut body));
+
+        assert_eq!(body, json!({ "unchanged": true }));
+
+        // no more changes
+        assert!(!tx.transform_body(&mut body));
+    }
+
+    #[test]
+    fn test_json_transform_rename() {
+        let tx = Json {
+            rename: vec![(path("rename_me"), path("renamed"))],
+            ..Default::default()
+        };
+
+        let mut body = json!({
+            "rename_me": "test",
+            "unchanged": true
+        });
+
+        assert!(tx.transform_body(&mut body));
+
+        assert_eq!(
+            body,
+            json!({
+                "renamed": "test",
+                "unchanged": true
+            })
+        );
+
+        // no more changes
+        assert!(!tx.transform_body(&mut body));
+    }
+
+    #[test]
+    fn test_json_transform_replace() {
+        let tx = Json {
+            replace: vec![(
+                path("replace_me"),
+                JsonValue::String("replacement".to_string()),
+            )],
+            ..Default::default()
+        };
+
+        let mut body = json!({
+            "replace_me": "test",
+            "unchanged": true
+        });
+
+        assert!(tx.transform_body(&mut body));
+
+        assert_eq!(
+            body,
+            json!({
+                "replace_me": "replacement",
+                "unchanged": true
+            })
+        );
+
+        // no more changes
+        assert!(!tx.transform_body(&mut body));
+    }
+
+    #[test]
+    fn test_json_transform_add() {
+        let tx = Json {
+            add: vec![(path("add_me"), JsonValue::String("added".to_string()))],
+            ..Default::default()
+        };
+
+        let mut body = json!({ "unchanged": true });
+
+        assert!(tx.transform_body(&mut body));
+
+        assert_eq!(
+            body,
+            json!({
+                "add_me": "added",
+                "unchanged": true
+            })
+        );
+
+        // no more changes
+        assert!(!tx.transform_body(&mut body));
+    }
+
+    #[test]
+    fn test_json_transform_append_absent() {
+        let tx = Json {
+            append: vec![(path("append_me"), JsonValue::String("appended".to_string()))],
+            ..Default::default()
+        };
+
+        let mut body = json!({ "unchanged": true });
+
+        assert!(tx.transform_body(&mut body));
+
+        assert_eq!(
+            body,
+            json!({
+                "append_me": [
+                    "appended"
+                ],
+                "unchanged": true
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_transform_append_array() {
+        let tx = Json {
+            append: vec![(path("append_me"), JsonValue::String("appended".to_string()))],
+            ..Default::default()
+        };
+
+        let mut body = json!({
+            "append_me": [
+                "current value"
+            ],
+            "unchanged": true
+        });
+
+        assert!(tx.transform_body(&mut body));
+
+        assert_eq!(
+            body,
+            json!({
+                "append_me": [
+                    "current value",
+                    "appended"
+                ],
+                "unchanged": true
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_transform_append_string() {
+        let tx = Json {
+            append: vec![(path("append_me"), JsonValue::String("appended".to_string()))],
+            ..Default::default()
+        };
+
+        let mut body = json!({
+            "append_me": "current value",
+            "unchanged": true
+        });
+
+        assert!(tx.transform_body(&mut body));
+
+        assert_eq!(
+            body,
+            json!({
+                "append_me": [
+                    "current value",
+                    "appended"
+                ],
+                "unchanged": true
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_transform_nested() {
+        let tx = Json {
+            remove: vec![path("data.user.password"), path("data.items[1]")],
+            rename: vec![(path("data.user.name"), path("data.profile.display_name"))],
+            replace: vec![(path("data.items[0].id"), json!(100))],
+            add: vec![(path("meta.version"), json!("v2"))],
+            append: vec![(path("data.tags"), json!("new"))],
+            ..Default::default()
+        };
+
+        let mut body = json!({
+            "data": {
+                "user": {
+                    "name": "alice",
+                    "password": "secret"
+                },
+                "items": [ { "id": 1 }, { "id": 2 } ],
+                "tags": [ "old" ]
+            }
+        });
+
+        assert!(tx.transform_body(&mut body));
+
+        assert_eq!(
+            body,
+            json!({
+                "data": {
+                    "user": {},
+                    "profile": { "display_name": "alice" },
+                    "items": [ { "id": 100 } ],
+                    "tags": [ "old", "new" ]
+                },
+                "meta": { "version": "v2" }
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_transform_nested_non_object_parent() {
+        let tx = Json {
+            rename: vec![(path("a"), path("s.a"))],
+            add: vec![(path("s.b"), json!(1)), (path("x.n[0].c"), json!(1))],
+            ..Default::default()
+        };
+
+        let mut body = json!({ "a": 1, "s": "string" });
+
+        assert!(!tx.transform_body(&mut body));
+        assert_eq!(body, json!({ "a": 1, "s": "string" }));
+    }
+
+    #[test]
+    fn test_json_required_keys() {
+        let tx = Json {
+            remove: vec![path("a.b"), path("**.secret")],
+            rename: vec![(path("items[*].name"), path("items[*].title"))],
+            replace: vec![(path("c"), json!(1))],
+            ..Default::default()
+        };
+
+        assert_eq!(Some(vec!["b", "secret", "name", "c"]), tx.required_keys());
+
+        let tx = Json {
+            remove: vec![path("a"), path("[0]")],
+            ..Default::default()
+        };
+        assert_eq!(None, tx.required_keys());
+
+        let tx = Json {
+            add: vec![(path("a"), json!(1))],
+            ..Default::default()
+        };
+        assert_eq!(None, tx.required_keys());
+    }
+
+    #[test]
+    fn test_config_invalid_json_path() {
+        let input: ConfigInput =
+            serde_json::from_str(r#"{ "remove": { "json": ["a..b"] } }"#).unwrap();
+        assert!(Config::try_from(input).is_err());
+
+        let input: ConfigInput =
+            serde_json::from_str(r#"{ "rename": { "json": ["a[0]:b"] } }"#).unwrap();
+        assert!(Config::try_from(input).is_err());
+    }
+
+    #[test]
+    fn test_json_transform_wildcards() {
+        let tx = Json {
+            remove: vec![path("items[*].internal_id"), path("**.secret")],
+            rename: vec![(path("items[*].name"), path("items[*].title"))],
+            replace: vec![(path("items[*].status"), json!("redacted"))],
+            add: vec![(path("items[*].meta.seen"), json!(true))],
+            append: vec![(path("items[*].tags"), json!("x"))],
+            ..Default::default()
+        };
+
+        let mut body = json!({
+            "items": [
+                { "internal_id": 1, "name": "a", "status": "ok", "secret": "s" },
+                { "internal_id": 2, "name": "b", "tags": [ "y" ] }
+            ],
+            "secret": "s"
+        });
+
+        assert!(tx.transform_body(&mut body));
+
+        assert_eq!(
+            body,
+            json!({
+                "items": [
+                    {
+                        "title": "a",
+                        "status": "redacted",
+                        "meta": { "seen": true },
+                        "tags": [ "x" ]
+                    },
+                    {
+                        "title": "b",
+                        "meta": { "seen": true },
+                        "tags": [ "y", "x" ]
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_config_invalid_wildcard_paths() {
+        let input: ConfigInput =
+            serde_json::from_str(r#"{ "add": { "json": ["items[*]:x"] } }"#).unwrap();
+        assert!(Config::try_from(input).is_err());
+
+        let input: ConfigInput =
+            serde_json::from_str(r#"{ "rename": { "json": ["items[*].a:b"] } }"#).unwrap();
+        assert!(Config::try_from(input).is_err());
+    }
+
+    #[test]
+    fn test_json_transform_array_elements() {
+        let tx = Json {
+            remove: vec![path("secret")],
+            ..Default::default()
+        };
+
+        let mut body = json!([ { "id": 1, "secret": "a" }, "string", { "id": 2 } ]);
+
+        assert!(tx.transform_array(&mut body));
+        assert_eq!(body, json!([ { "id": 1 }, "string", { "id": 2 } ]));
+
+        // no more changes
+        assert!(!tx.transform_array(&mut body));
+    }
+
+    #[test]
+    fn test_json_transform_array_root() {
+        let tx = Json {
+            remove: vec![path("[0]"), path("[*].secret")],
+            array_mode: ArrayMode::Root,
+            ..Default::default()
+        };
+
+        let mut body = json!([ "first", { "id": 1, "secret": "a" } ]);
+
+        assert!(tx.transform_array(&mut body));
+        assert_eq!(body, json!([ { "id": 1 } ]));
+    }
+
+    #[test]
+    fn test_json_transform_array_skip() {
+        let tx = Json {
+            remove: vec![path("secret")],
+            array_mode: ArrayMode::Skip,
+            ..Default::default()
+        };
+
+        let mut body = json!([ { "id": 1, "secret": "a" } ]);
+
+        assert!(!tx.transform_array(&mut body));
+        assert_eq!(body, json!([ { "id": 1, "secret": "a" } ]));
+    }
+
+    #[test]
+    fn test_json_transform_array_patch() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "remove": { "json": ["secret"] },
+            "patch": [
+                { "op": "test", "path": "/0/id", "value": 1 },
+                { "op": "add", "path": "/-", "value": { "id": 3 } }
+            ]
+        }))
+        .unwrap();
+
+        let mut tx = Config::try_from(input)
+            .unwrap()
+            .rules
+            .remove(0)
+            .json
+            .unwrap();
+
+        let mut body = json!([ { "id": 1, "secret": "a" }, { "id": 2 } ]);
+
+        assert!(tx.transform(&mut body));
+        assert_eq!(body, json!([ { "id": 1 }, { "id": 2 }, { "id": 3 } ]));
+
+        tx.array_mode = ArrayMode::Skip;
+        let mut body = json!([ { "id": 1, "secret": "a" } ]);
+
+        assert!(tx.transform(&mut body));
+        assert_eq!(body, json!([ { "id": 1, "secret": "a" }, { "id": 3 } ]));
+    }
+
+    #[test]
+    fn test_config_array_mode() {
+        let input: ConfigInput =
+            serde_json::from_str(r#"{ "remove": { "json": ["a"] }, "array_mode": "root" }"#)
+                .unwrap();
+        let mut config = Config::try_from(input).unwrap();
+        assert_eq!(
+            ArrayMode::Root,
+            config.rules.remove(0).json.unwrap().array_mode
+        );
+
+        assert!(serde_json::from_str::<ConfigInput>(r#"{ "array_mode": "nope" }"#).is_err());
+    }
+
+    #[test]
+    fn test_json_transform_patch() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "remove": { "json": ["secret"] },
+            "patch": [
+                { "op": "test", "path": "/secret", "value": null },
+                { "op": "add", "path": "/meta", "value": { "version": 2 } }
+            ]
+        }))
+        .unwrap();
+
+        let tx = Config::try_from(input)
+            .unwrap()
+            .rules
+            .remove(0)
+            .json
+            .unwrap();
+
+        let mut body = json!({ "secret": "s", "id": 1 });
+
+        // the patch runs after `remove`, so the test operation fails
+        assert!(tx.transform_body(&mut body));
+        assert_eq!(body, json!({ "id": 1 }));
+    }
+
+    #[test]
+    fn test_config_patch_only() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "patch": [{ "op": "remove", "path": "/a" }]
+        }))
+        .unwrap();
+
+        let tx = Config::try_from(input)
+            .unwrap()
+            .rules
+            .remove(0)
+            .json
+            .unwrap();
+
+        let mut body = json!({ "a": 1, "b": 2 });
+        assert!(tx.transform_body(&mut body));
+        assert_eq!(body, json!({ "b": 2 }));
+    }
+
+    #[test]
+    fn test_json_transform_merge() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "merge": {
+                "meta": { "version": 2 },
+                "debug": null
+            },
+            "patch": [
+                { "op": "test", "path": "/meta/version", "value": 2 }
+            ]
+        }))
+        .unwrap();
+
+        let tx = Config::try_from(input)
+            .unwrap()
+            .rules
+            .remove(0)
+            .json
+            .unwrap();
+
+        let mut body = json!({ "id": 1, "debug": { "trace": true }, "meta": { "a": 1 } });
+
+        // the merge patch is applied before the JSON patch
+        assert!(tx.transform_body(&mut body));
+        assert_eq!(body, json!({ "id": 1, "meta": { "a": 1, "version": 2 } }));
+
+        // no more changes
+        assert!(!tx.transform_body(&mut body));
+    }
 
-        let mut changed = false;
+    #[test]
+    fn test_config_merge_must_be_object() {
+        assert!(serde_json::from_value::<ConfigInput>(json!({ "merge": [1] })).is_err());
+        assert!(serde_json::from_value::<ConfigInput>(json!({ "merge": "a" })).is_err());
+    }
 
-        for field in &self.remove {
-            if body.remove(field).is_some() {
-                info!("removed field {:?}", field);
-                changed = true;
+    #[test]
+    fn test_json_transform_add_structured() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "add": {
+                "json": [r#"links:{"self":"/x"}"#, "tags:[]", "deleted:null"],
+                "json_types": ["object", "array", "null"]
             }
-        }
+        }))
+        .unwrap();
 
-        for KeyValue(from, to) in &self.rename {
-            if let Some(v) = body.remove(from) {
-                let _ = body.insert(to.clone(), v);
-                info!("renamed {} => {}", from, to);
-                changed = true;
-            }
-        }
+        let tx = Config::try_from(input)
+            .unwrap()
+            .rules
+            .remove(0)
+            .json
+            .unwrap();
 
-        for (field, value) in &self.replace {
-            if let Some(found) = body.get_mut(field) {
-                if found != value {
-                    info!("replacing field {:?} {:?} => {:?}", field, found, value);
-                    *found = value.clone();
-                    changed = true;
-                }
-            }
-        }
+        let mut body = json!({ "id": 1 });
 
-        for (field, value) in &self.add {
-            if !body.contains_key(field) {
-                info!("adding field {:?} {:?}", field, value);
-                body.insert(field.to_owned(), value.clone());
-                changed = true;
-            }
-        }
+        assert!(tx.transform_body(&mut body));
+        assert_eq!(
+            body,
+            json!({
+                "id": 1,
+                "links": { "self": "/x" },
+                "tags": [],
+                "deleted": null
+            })
+        );
+    }
 
-        for (field, value) in &self.append {
-            body.entry(field)
-                .and_modify(|found| {
-                    let current = found.take();
-                    let mut appended = false;
-
-                    *found = match current {
-                        JsonValue::String(_) => {
-                            appended = true;
-                            serde_json::json!([current, value.clone()])
-                        }
-                        JsonValue::Array(mut arr) => {
-                            appended = true;
-                            arr.push(value.clone());
-                            arr.into()
-                        }
-                        // XXX: this branch is not fully compatible with the Lua plugin
-                        //
-                        // The lua plugin doesn't attempt to disambiguate between an
-                        // array-like table and a map-like table. It just blindly calls
-                        // the `table.insert()` function.
-                        _ => current,
-                    };
-
-                    if appended {
-                        changed = true;
-                        info!("appended {:?} to {:?}", value, field);
-                    }
-                })
-                .or_insert_with(|| {
-                    changed = true;
-                    let new = serde_json::json!([value]);
-                    info!("inserted {:?} to {:?}", new, field);
-                    new
-                });
+    #[test]
+    fn test_config_invalid_casts() {
+        let cases = [
+            (
+                json!({ "add": { "json": ["a:1", "b:abc"], "json_types": ["number", "number"] } }),
+                r#"add.json[1]: Invalid number value: "abc""#,
+            ),
+            (
+                json!({ "replace": { "json": ["a:yes"], "json_types": ["boolean"] } }),
+                r#"replace.json[0]: Invalid boolean value: "yes""#,
+            ),
+            (
+                json!({ "append": { "json": ["a:[1"], "json_types": ["array"] } }),
+                r#"append.json[0]: Invalid array value: "[1""#,
+            ),
+            (
+                json!({ "add": { "json": ["a:1", "b:2"], "json_types": ["number"] } }),
+                "add.json_types: expected 2 entries (one per add.json entry), got 1",
+            ),
+            (
+                json!({ "add": { "json": ["a:1"], "json_types": ["number", "string"] } }),
+                "add.json_types: expected 1 entries (one per add.json entry), got 2",
+            ),
+            (
+                json!({ "remove": { "json": ["a"], "json_types": ["number"] } }),
+                "remove.json_types: not supported for this section",
+            ),
+            (
+                json!({ "remove": { "json": ["a", "b..c"] } }),
+                r#"remove.json[1]: Invalid JSON path "b..c": empty key"#,
+            ),
+        ];
+
+        for (input, expect) in cases {
+            let input: ConfigInput = serde_json::from_value(input).unwrap();
+            assert_eq!(expect, Config::try_from(input).unwrap_err().to_string());
         }
+    }
 
-        changed
+    #[test]
+    fn test_config_json_types_optional() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "add": { "json": ["a:1", "b:true"] }
+        }))
+        .unwrap();
+
+        let tx = Config::try_from(input)
+            .unwrap()
+            .rules
+            .remove(0)
+            .json
+            .unwrap();
+        assert_eq!(
+            tx.add,
+            vec![(path("a"), json!("1")), (path("b"), json!("true"))]
+        );
     }
-}
 
-#[derive(Default, Debug, Clone)]
-pub(crate) struct Config {
-    pub(crate) headers: Option<Headers>,
-    pub(crate) json: Option<Json>,
-}
+    #[test]
+    fn test_config_invalid_cast_type() {
+        let err = serde_json::from_value::<ConfigInput>(json!({
+            "add": { "json": ["a:1"], "json_types": ["float"] }
+        }))
+        .unwrap_err();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert!(err.to_string().contains(r#"Invalid JSON type: "float""#));
+    }
 
-    macro_rules! map {
-        ($x:tt) => {{
-            let json = serde_json::json!($x);
+    #[test]
+    fn test_config_header_templates() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "add": {
+                "headers": [
+                    "x-id:$(request.header.X-Request-ID)",
+                    "x-static:static"
+                ]
+            },
+            "append": {
+                "headers": ["x-debug:$(response.status) $(request.header.x-request-id)"]
+            }
+        }))
+        .unwrap();
 
-            let serde_json::Value::Object(map) = json else {
-                panic!("not a json object");
-            };
-            map
-        }};
-    }
+        let mut config = Config::try_from(input).unwrap();
+        assert_eq!(vec!["x-request-id".to_string()], config.request_headers);
 
-    impl KeyValue {
-        #[warn(unused)]
-        pub(crate) fn new<T: std::string::ToString>(name: T, value: T) -> Self {
-            KeyValue(name.to_string(), value.to_string())
-        }
+        let headers = config.rules.remove(0).headers.unwrap();
+        assert_eq!("x-static", headers.add[1].0);
+        assert_eq!("static", headers.add[1].1.render(|_| None));
     }
 
     #[test]
-    fn test_header_try_from_valid() {
-        assert_eq!(Ok(KeyValue::new("a", "b")), KeyValue::try_from("a:b"));
-    }
+    fn test_config_escaped_templates() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "add": {
+                "headers": ["x-cost:cost $$(USD)"],
+                "json": ["cost:cost $$(USD)", "script:echo $$(date)"]
+            }
+        }))
+        .unwrap();
 
-    #[test]
-    fn test_header_try_from_invalid() {
-        assert_eq!(
-            Err(InvalidKeyValue("a".to_string())),
-            KeyValue::try_from("a")
-        );
+        let rule = Config::try_from(input).unwrap().rules.remove(0);
         assert_eq!(
-            Err(InvalidKeyValue("a:".to_string())),
-            KeyValue::try_from("a:")
+            "cost $(USD)",
+            rule.headers.unwrap().add[0].1.render(|_| None)
         );
+
+        let tx = rule.json.unwrap();
+        assert!(tx.templates.is_empty());
+        assert_eq!(json!("cost $(USD)"), tx.add[0].1);
+        assert_eq!(json!("echo $(date)"), tx.add[1].1);
+    }
+
+    #[test]
+    fn test_config_invalid_header_template() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "replace": { "headers": ["x-id:$(nope)"] }
+        }))
+        .unwrap();
+
         assert_eq!(
-            Err(InvalidKeyValue(":b".to_string())),
-            KeyValue::try_from(":b")
+            r#"replace.headers[0]: Invalid template "$(nope)": unknown template variable"#,
+            Config::try_from(input).unwrap_err().to_string()
         );
     }
 
     #[test]
-    fn test_json_deserialize_transformations() {
-        assert_eq!(
-            TransformationsConfig {
-                headers: vec![KeyValue::new("a", "b"), KeyValue::new("c", "d")],
-                ..Default::default()
+    fn test_json_resolve_templates() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "add": {
+                "json": [
+                    "trace_id:$(request.header.traceparent)",
+                    "status:$(response.status)",
+                    "static:value"
+                ],
+                "json_types": ["string", "number", "string"]
             },
-            serde_json::from_str(r#"{ "headers": ["a:b", "c:d"] }"#).unwrap()
+            "replace": {
+                "json": ["served_by:$(response.header.server)"]
+            }
+        }))
+        .unwrap();
+
+        let mut config = Config::try_from(input).unwrap();
+        assert_eq!(vec!["traceparent".to_string()], config.request_headers);
+
+        let tx = config.rules.remove(0).json.unwrap();
+        let resolved = tx.resolve(|t| {
+            t.render(|v| match v {
+                Variable::RequestHeader(_) => Some("00-abc-01".to_string()),
+                Variable::ResponseHeader(_) => Some("upstream".to_string()),
+                Variable::ResponseStatus => Some("200".to_string()),
+                _ => None,
+            })
+        });
+
+        let mut body = json!({ "served_by": "origin" });
+
+        assert!(resolved.transform_body(&mut body));
+        assert_eq!(
+            body,
+            json!({
+                "trace_id": "00-abc-01",
+                "status": 200,
+                "static": "value",
+                "served_by": "upstream"
+            })
         );
     }
 
     #[test]
-    fn test_json_transform_remove() {
+    fn test_json_resolve_invalid_cast() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "add": {
+                "json": ["status:$(response.status)"],
+                "json_types": ["number"]
+            }
+        }))
+        .unwrap();
+
+        let tx = Config::try_from(input)
+            .unwrap()
+            .rules
+            .remove(0)
+            .json
+            .unwrap();
+        let resolved = tx.resolve(|_| "nope".to_string());
+
+        assert_eq!(resolved.add, vec![(path("status"), json!("nope"))]);
+    }
+
+    #[test]
+    fn test_json_resolve_no_templates() {
         let tx = Json {
-            remove: vec!["remove_me".to_string()],
+            add: vec![(path("a"), json!(1))],
             ..Default::default()
         };
 
-        let mut body = map!({
-            "remove_me": "goodbye",
-            "unchanged": true
-        });
+        assert!(matches!(tx.resolve(|_| unreachable!()), Cow::Borrowed(_)));
+    }
 
-        assert!(tx.transform_body(&mut body));
+    #[test]
+    fn test_config_if_status() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "if_status": ["2xx", "4xx"],
+            "remove": {
+                "headers": ["x-debug"],
+                "json": ["debug"],
+                "if_status": ["2xx"]
+            },
+            "add": {
+                "headers": ["x-added:$(response.status)"],
+                "json": ["status:$(response.status)"],
+                "json_types": ["number"]
+            }
+        }))
+        .unwrap();
 
-        assert_eq!(body, map!({ "unchanged": true }));
+        let rule = Config::try_from(input).unwrap().rules.remove(0);
 
-        // no more changes
-        assert!(!tx.transform_body(&mut body));
+        assert!(rule.if_status.matches(Some(200)));
+        assert!(rule.if_status.matches(Some(404)));
+        assert!(!rule.if_status.matches(Some(500)));
+
+        let headers = rule.headers.unwrap();
+        assert!(matches!(headers.for_status(Some(200)), Cow::Borrowed(_)));
+
+        let filtered = headers.for_status(Some(404));
+        assert!(filtered.remove.is_empty());
+        assert_eq!(1, filtered.add.len());
+
+        let tx = rule.json.unwrap();
+        assert!(matches!(tx.for_status(Some(204)), Cow::Borrowed(_)));
+
+        let filtered = tx.for_status(Some(404));
+        let resolved = filtered.resolve(|_| "404".to_string());
+
+        let mut body = json!({ "debug": true });
+        assert!(resolved.transform_body(&mut body));
+        assert_eq!(body, json!({ "debug": true, "status": 404 }));
     }
 
     #[test]
-    fn test_json_transform_rename() {
-        let tx = Json {
-            rename: vec![KeyValue::from(("rename_me", "renamed"))],
-            ..Default::default()
-        };
+    fn test_json_for_status_templates() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "replace": {
+                "json": ["a:$(response.status)"],
+                "if_status": ["5xx"]
+            },
+            "add": {
+                "json": ["b:$(response.status)"]
+            }
+        }))
+        .unwrap();
+
+        let tx = Config::try_from(input)
+            .unwrap()
+            .rules
+            .remove(0)
+            .json
+            .unwrap();
+
+        // templates for filtered sections must be dropped along with them
+        let filtered = tx.for_status(Some(200));
+        let resolved = filtered.resolve(|_| "200".to_string());
+
+        assert!(resolved.replace.is_empty());
+        assert_eq!(resolved.add, vec![(path("b"), json!("200"))]);
+    }
 
-        let mut body = map!({
-            "rename_me": "test",
-            "unchanged": true
-        });
+    #[test]
+    fn test_config_rules() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "remove": { "headers": ["server"] },
+            "rules": [
+                {
+                    "match": {
+                        "path": ["/api/**"],
+                        "headers": ["X-Tenant:acme"]
+                    },
+                    "add": { "json": ["tenant:acme"] },
+                    "if_status": ["2xx"]
+                },
+                {
+                    "match": { "method": ["POST"] },
+                    "add": { "headers": ["x-id:$(request.header.x-request-id)"] }
+                }
+            ]
+        }))
+        .unwrap();
 
-        assert!(tx.transform_body(&mut body));
+        let config = Config::try_from(input).unwrap();
 
+        assert_eq!(3, config.rules.len());
+        assert!(config.rules[0].headers.is_some());
+        assert!(config.rules[1].json.is_some());
+        assert!(config.rules[2].headers.is_some());
         assert_eq!(
-            body,
-            map!({
-                "renamed": "test",
-                "unchanged": true
-            })
+            vec!["x-tenant".to_string(), "x-request-id".to_string()],
+            config.request_headers
         );
 
-        // no more changes
-        assert!(!tx.transform_body(&mut body));
+        let mut request = RequestInfo {
+            method: Some("GET".to_string()),
+            path: Some("/api/users".to_string()),
+            ..Default::default()
+        };
+        request
+            .headers
+            .insert("x-tenant".to_string(), "acme".to_string());
+
+        let matching: Vec<bool> = config
+            .rules
+            .iter()
+            .map(|rule| rule.matches(&request, Some(200), None))
+            .collect();
+        assert_eq!(vec![true, true, false], matching);
+
+        assert!(!config.rules[1].matches(&request, Some(404), None));
     }
 
     #[test]
-    fn test_json_transform_replace() {
-        let tx = Json {
-            replace: vec![(
-                "replace_me".to_string(),
-                JsonValue::String("replacement".to_string()),
-            )],
-            ..Default::default()
-        };
+    fn test_config_empty_default_rule() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "rules": [{ "remove": { "json": ["secret"] } }]
+        }))
+        .unwrap();
+
+        let config = Config::try_from(input).unwrap();
+
+        assert_eq!(1, config.rules.len());
+        assert!(config.rules[0].if_status.matches(Some(500)));
+
+        for (mut input, field) in [
+            (json!({ "if_status": ["2xx"] }), "if_status"),
+            (json!({ "match": { "path": ["/api/**"] } }), "match"),
+        ] {
+            input["rules"] = json!([{ "remove": { "json": ["secret"] } }]);
+
+            let input: ConfigInput = serde_json::from_value(input).unwrap();
+            let err = Config::try_from(input).unwrap_err();
+
+            assert!(
+                err.to_string().starts_with(&format!("{}: ", field)),
+                "{}",
+                err
+            );
+        }
+    }
 
-        let mut body = map!({
-            "replace_me": "test",
-            "unchanged": true
-        });
+    #[test]
+    fn test_config_max_body_size() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "remove": { "json": ["secret"] },
+            "max_body_size": 1048576,
+            "on_oversize": "truncate-headers-only"
+        }))
+        .unwrap();
+
+        let config = Config::try_from(input).unwrap();
+        assert_eq!(Some(1048576), config.max_body_size);
+        assert_eq!(OversizePolicy::TruncateHeadersOnly, config.on_oversize);
+        assert_eq!(1, config.rules.len());
+
+        let input: ConfigInput = serde_json::from_value(json!({})).unwrap();
+        let config = Config::try_from(input).unwrap();
+        assert_eq!(None, config.max_body_size);
+        assert_eq!(OversizePolicy::Passthrough, config.on_oversize);
+
+        for input in [
+            json!({ "on_oversize": "truncate" }),
+            json!({ "max_body_size": -1 }),
+        ] {
+            assert!(serde_json::from_value::<ConfigInput>(input).is_err());
+        }
+    }
 
-        assert!(tx.transform_body(&mut body));
+    #[test]
+    fn test_config_on_compressed() {
+        let input: ConfigInput = serde_json::from_value(json!({})).unwrap();
+        let config = Config::try_from(input).unwrap();
+        assert_eq!(CompressedPolicy::Recompress, config.on_compressed);
+
+        let input: ConfigInput =
+            serde_json::from_value(json!({ "on_compressed": "decompress" })).unwrap();
+        let config = Config::try_from(input).unwrap();
+        assert_eq!(CompressedPolicy::Decompress, config.on_compressed);
+
+        assert!(
+            serde_json::from_value::<ConfigInput>(json!({ "on_compressed": "strip" })).is_err()
+        );
+    }
 
+    #[test]
+    fn test_config_content_types() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "content_types": ["text/*"],
+            "json_without_content_type": true,
+            "sniff_json": true
+        }))
+        .unwrap();
+
+        let config = Config::try_from(input).unwrap();
+        assert!(config.content_types.is_json(Some("text/plain")));
+        assert!(!config.content_types.is_json(Some("application/json")));
+        assert!(config.content_types.is_json(None));
+        assert!(config.content_types.sniff);
+
+        let input: ConfigInput =
+            serde_json::from_value(json!({ "content_types": ["json"] })).unwrap();
         assert_eq!(
-            body,
-            map!({
-                "replace_me": "replacement",
-                "unchanged": true
-            })
+            r#"content_types: Invalid content type pattern "json": expected <type>/<subtype>"#,
+            Config::try_from(input).unwrap_err().to_string()
         );
-
-        // no more changes
-        assert!(!tx.transform_body(&mut body));
     }
 
     #[test]
-    fn test_json_transform_add() {
-        let tx = Json {
-            add: vec![("add_me".to_string(), JsonValue::String("added".to_string()))],
-            ..Default::default()
-        };
-
-        let mut body = map!({ "unchanged": true });
+    fn test_config_invalid_rules() {
+        let cases = [
+            (
+                json!({ "rules": [{}, { "add": { "json": ["a:b"], "json_types": ["number"] } }] }),
+                r#"rules[1].add.json[0]: Invalid number value: "b""#,
+            ),
+            (
+                json!({ "rules": [{ "match": { "headers": [":a"] } }] }),
+                r#"rules[0].match: Invalid headers pattern ":a": empty name"#,
+            ),
+        ];
+
+        for (input, expect) in cases {
+            let input: ConfigInput = serde_json::from_value(input).unwrap();
+            assert_eq!(expect, Config::try_from(input).unwrap_err().to_string());
+        }
+    }
 
-        assert!(tx.transform_body(&mut body));
+    #[test]
+    fn test_config_invalid_xml() {
+        let cases = [
+            (
+                json!({ "remove": { "xml": ["Envelope"] } }),
+                r#"remove.xml[0]: Invalid XML path "Envelope": path cannot refer to the root element"#,
+            ),
+            (
+                json!({ "rename": { "xml": ["a.b:c.d"] } }),
+                r#"rename.xml[0]: Invalid XML path "c.d": expected an element or attribute name"#,
+            ),
+            (
+                json!({ "remove": { "xml": ["soap:Envelope.soap:Body"] } }),
+                r#"remove.xml[0]: Invalid XML path "soap:Envelope.soap:Body": names cannot have a namespace prefix"#,
+            ),
+            (
+                json!({ "add": { "xml": ["a.*:x"] } }),
+                r#"add.xml[0]: Invalid XML path "a.*": path must end with an element name"#,
+            ),
+            (
+                json!({ "append": { "xml": ["a@id:1"] } }),
+                r#"append.xml[0]: Invalid XML path "a@id": path cannot refer to an attribute"#,
+            ),
+            (
+                json!({ "request": { "remove": { "xml": ["a.b"] } } }),
+                "request.remove.xml: only supported for response transformations",
+            ),
+        ];
+
+        for (input, expect) in cases {
+            let input: ConfigInput = serde_json::from_value(input).unwrap();
+            assert_eq!(expect, Config::try_from(input).unwrap_err().to_string());
+        }
+    }
 
+    #[test]
+    fn test_xml_transform() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "remove": { "xml": ["**.password", "**.User@internal"] },
+            "rename": { "xml": ["**.User@id:userId"] },
+            "replace": { "xml": ["**.User.name:$(request.header.x-name)"] },
+            "add": { "xml": ["**.User.role:user"], "if_status": ["404"] },
+            "append": { "xml": ["**.User.tag:a"] }
+        }))
+        .unwrap();
+
+        let config = Config::try_from(input).unwrap();
+        assert_eq!(vec!["x-name".to_string()], config.request_headers);
+
+        let rule = &config.rules[0];
+        assert!(rule.json.is_none());
+
+        let xml_tx = rule.xml.as_ref().unwrap().for_status(Some(200));
+        let mut doc = Document::parse(
+            "<Envelope><Body><User id=\"1\" internal=\"x\"><name>a</name>\
+             <password>p</password></User></Body></Envelope>",
+        )
+        .unwrap();
+
+        assert!(xml_tx.transform(&mut doc, |t| t.render(|_| Some("b".to_string()))));
         assert_eq!(
-            body,
-            map!({
-                "add_me": "added",
-                "unchanged": true
-            })
+            "<Envelope><Body><User userId=\"1\"><name>b</name><tag>a</tag></User></Body>\
+             </Envelope>",
+            doc.to_string()
         );
+    }
 
-        // no more changes
-        assert!(!tx.transform_body(&mut body));
+    #[test]
+    fn test_config_response_form() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "remove": { "form": ["refresh_token"] },
+            "add": { "form": ["source:$(request.header.x-source)"], "if_status": ["2xx"] }
+        }))
+        .unwrap();
+
+        let config = Config::try_from(input).unwrap();
+        assert_eq!(vec!["x-source".to_string()], config.request_headers);
+
+        let rule = &config.rules[0];
+        assert!(rule.headers.is_none());
+        assert!(rule.request.is_none());
+
+        let form_tx = rule.form.as_ref().unwrap();
+        assert_eq!(vec!["refresh_token".to_string()], form_tx.remove);
+        assert!(form_tx.for_status(Some(401)).add.is_empty());
+        assert_eq!(1, form_tx.for_status(Some(200)).add.len());
     }
 
     #[test]
-    fn test_json_transform_append_absent() {
-        let tx = Json {
-            append: vec![(
-                "append_me".to_string(),
-                JsonValue::String("appended".to_string()),
-            )],
-            ..Default::default()
-        };
+    fn test_config_regex_replace() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "body": { "regex_replace": [r"(\w+)\.corp\.internal => $1.example.com"] },
+            "rules": [{
+                "match": { "content_type": ["text/html"] },
+                "body": { "regex_replace": ["10\\.0\\.0\\.1 => [redacted]"] }
+            }]
+        }))
+        .unwrap();
+
+        let config = Config::try_from(input).unwrap();
+        assert_eq!(2, config.rules.len());
+        assert!(config.rules[0].json.is_none());
+        assert_eq!(
+            r"(\w+)\.corp\.internal => $1.example.com",
+            config.rules[0].regex_replace[0].to_string()
+        );
 
-        let mut body = map!({ "unchanged": true });
+        for (input, err) in [
+            (
+                json!({ "body": { "regex_replace": ["a => b", "a"] } }),
+                "body.regex_replace[1]: Invalid regex_replace \"a\": \
+                 expected <pattern> => <replacement>",
+            ),
+            (
+                json!({ "rules": [{ "body": { "regex_replace": ["( => b"] } }] }),
+                "rules[0].body.regex_replace[0]: Invalid regex_replace \"( => b\"",
+            ),
+        ] {
+            let input: ConfigInput = serde_json::from_value(input).unwrap();
+            let actual = Config::try_from(input).unwrap_err().to_string();
+            assert!(actual.starts_with(err), "{}", actual);
+        }
+    }
 
-        assert!(tx.transform_body(&mut body));
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
 
+    #[test]
+    fn test_fields_transform() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "remove": { "headers": ["X-Remove"] },
+            "rename": { "headers": ["x-old:x-new"] },
+            "replace": { "headers": ["x-multi:one", "x-missing:nope"] },
+            "add": { "headers": ["x-added:$(response.status)", "x-new:ignored"] },
+            "append": { "headers": ["x-multi:two"] }
+        }))
+        .unwrap();
+
+        let tx = Config::try_from(input)
+            .unwrap()
+            .rules
+            .remove(0)
+            .headers
+            .unwrap();
+
+        let mut headers = pairs(&[
+            ("x-remove", "a"),
+            ("x-old", "b"),
+            ("x-new", "c"),
+            ("x-multi", "d"),
+            ("x-remove", "e"),
+            ("x-multi", "f"),
+        ]);
+
+        assert!(tx.transform(FieldKind::Header, &mut headers, |t| {
+            t.render(|_| Some("200".to_string()))
+        }));
         assert_eq!(
-            body,
-            map!({
-                "append_me": [
-                    "appended"
-                ],
-                "unchanged": true
-            })
+            pairs(&[
+                ("x-new", "b"),
+                ("x-multi", "one"),
+                ("x-added", "200"),
+                ("x-multi", "two"),
+            ]),
+            headers
         );
     }
 
     #[test]
-    fn test_json_transform_append_array() {
-        let tx = Json {
-            append: vec![(
-                "append_me".to_string(),
-                JsonValue::String("appended".to_string()),
-            )],
-            ..Default::default()
+    fn test_fields_transform_case_sensitivity() {
+        let tx = Fields {
+            remove: vec!["a".to_string()],
+            rename: vec![],
+            replace: vec![],
+            add: vec![],
+            append: vec![],
+            if_status: SectionStatus::default(),
         };
 
-        let mut body = map!({
-            "append_me": [
-                "current value"
-            ],
-            "unchanged": true
-        });
+        let mut fields = pairs(&[("A", "1"), ("a", "2")]);
 
-        assert!(tx.transform_body(&mut body));
+        assert!(tx.transform(FieldKind::QueryArg, &mut fields, |_| unreachable!()));
+        assert_eq!(pairs(&[("A", "1")]), fields);
 
-        assert_eq!(
-            body,
-            map!({
-                "append_me": [
-                    "current value",
-                    "appended"
-                ],
-                "unchanged": true
-            })
-        );
+        assert!(tx.transform(FieldKind::Header, &mut fields, |_| unreachable!()));
+        assert!(fields.is_empty());
+
+        assert!(!tx.transform(FieldKind::Header, &mut fields, |_| unreachable!()));
     }
 
     #[test]
-    fn test_json_transform_append_string() {
-        let tx = Json {
-            append: vec![(
-                "append_me".to_string(),
-                JsonValue::String("appended".to_string()),
-            )],
-            ..Default::default()
-        };
+    fn test_config_request() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "request": {
+                "remove": { "querystring": ["debug"], "json": ["password"] },
+                "add": {
+                    "headers": ["x-tenant:$(request.query.tenant)"],
+                    "form": ["source:gateway"],
+                    "json": ["count:1"],
+                    "json_types": ["number"]
+                }
+            },
+            "remove": { "headers": ["server"] }
+        }))
+        .unwrap();
 
-        let mut body = map!({
-            "append_me": "current value",
-            "unchanged": true
-        });
+        let rule = Config::try_from(input).unwrap().rules.remove(0);
+        let request = rule.request.unwrap();
 
-        assert!(tx.transform_body(&mut body));
+        assert!(rule.headers.is_some());
+        assert!(rule.json.is_none());
 
         assert_eq!(
-            body,
-            map!({
-                "append_me": [
-                    "current value",
-                    "appended"
-                ],
-                "unchanged": true
-            })
+            vec!["debug".to_string()],
+            request.querystring.unwrap().remove
         );
+        assert_eq!("x-tenant", request.headers.unwrap().add[0].0);
+        assert_eq!("source", request.form.unwrap().add[0].0);
+
+        let json = request.json.unwrap();
+        assert_eq!(vec![path("password")], json.remove);
+        assert_eq!(vec![(path("count"), json!(1))], json.add);
+    }
+
+    #[test]
+    fn test_config_request_only() {
+        let input: ConfigInput = serde_json::from_value(json!({
+            "rules": [{
+                "match": { "path": ["/login"] },
+                "request": { "add": { "headers": ["x-login:true"] } }
+            }]
+        }))
+        .unwrap();
+
+        let config = Config::try_from(input).unwrap();
+
+        assert_eq!(1, config.rules.len());
+        assert!(config.rules[0].headers.is_none());
+
+        let request = RequestInfo {
+            path: Some("/login".to_string()),
+            ..Default::default()
+        };
+        assert!(config.rules[0].matches_request(&request));
+        assert!(!config.rules[0].matches_request(&RequestInfo::default()));
+    }
+
+    #[test]
+    fn test_config_invalid_request() {
+        let cases = [
+            (
+                json!({ "add": { "querystring": ["a:b"] } }),
+                "add.querystring: only supported for request transformations",
+            ),
+            (
+                json!({ "rename": { "querystring": ["a:b"] } }),
+                "rename.querystring: only supported for request transformations",
+            ),
+            (
+                json!({ "request": { "remove": { "headers": ["a"], "if_status": ["2xx"] } } }),
+                "request.remove.if_status: not supported for request transformations",
+            ),
+            (
+                json!({ "request": { "add": { "querystring": ["a:$(response.status)"] } } }),
+                "request.add.querystring[0]: response values are not available to request \
+                 transformations",
+            ),
+            (
+                json!({ "request": { "replace": { "json": ["a:$(response.header.b)"] } } }),
+                "request.replace.json[0]: response values are not available to request \
+                 transformations",
+            ),
+            (
+                json!({ "request": { "add": { "headers": ["a:$(nope)"] } } }),
+                r#"request.add.headers[0]: Invalid template "$(nope)": unknown template variable"#,
+            ),
+            (
+                json!({ "if_status": ["2xx"], "request": { "remove": { "headers": ["a"] } } }),
+                "request: request transformations cannot be combined with response conditions \
+                 (if_status, match.content_type)",
+            ),
+            (
+                json!({ "rules": [{
+                    "match": { "content_type": ["application/json"] },
+                    "request": { "remove": { "headers": ["a"] } }
+                }] }),
+                "rules[0].request: request transformations cannot be combined with response \
+                 conditions (if_status, match.content_type)",
+            ),
+        ];
+
+        for (input, expect) in cases {
+            let input: ConfigInput = serde_json::from_value(input).unwrap();
+            assert_eq!(expect, Config::try_from(input).unwrap_err().to_string());
+        }
     }
 }
@@ -0,0 +1,889 @@
+//! XML documents and element paths.
+//!
+//! Paths use dot notation to descend from the root element, e.g.
+//! `Envelope.Body.GetUserResponse.password`, and may end with `@name` to refer
+//! to an attribute rather than an element (`Envelope.Body.User@id`). Names are
+//! matched against local names, so namespace prefixes are ignored: `Envelope`
+//! matches `<soap:Envelope>`.
+//!
+//! Paths may also fan out to multiple elements:
+//!
+//! * `*` selects every child element (`Envelope.Body.*.password`)
+//! * `**` selects an element and all of its descendants (`**.password`)
+//!
+//! Documents keep the markup they were parsed from, so only the elements,
+//! attributes and text that are changed are written differently.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+fn is_ws(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_whitespace())
+}
+
+/// Returns whether a string can be used as an element or attribute name in a
+/// path. This is stricter than XML itself, as `.` separates path steps.
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_' || !c.is_ascii())
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '-' | '_') || !c.is_ascii())
+}
+
+fn local_name(name: &str) -> &str {
+    name.split_once(':').map_or(name, |(_, local)| local)
+}
+
+/// Returns `name` renamed to `to`, keeping its namespace prefix.
+fn renamed(name: &str, to: &str) -> String {
+    match name.split_once(':') {
+        Some((prefix, _)) => format!("{}:{}", prefix, to),
+        None => to.to_owned(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidXmlPath {
+    path: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for InvalidXmlPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid XML path {:?}: {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidXmlPath {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Name(String),
+    AnyChild,
+    Descendants,
+}
+
+impl Step {
+    fn matches(&self, element: &Element) -> bool {
+        match self {
+            Step::Name(name) => local_name(&element.name) == name,
+            Step::AnyChild | Step::Descendants => true,
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Name(name) => write!(f, "{}", name),
+            Step::AnyChild => write!(f, "*"),
+            Step::Descendants => write!(f, "**"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct XmlPath {
+    steps: Vec<Step>,
+    attribute: Option<String>,
+}
+
+impl TryFrom<&str> for XmlPath {
+    type Error = InvalidXmlPath;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let err = |reason| InvalidXmlPath {
+            path: input.to_owned(),
+            reason,
+        };
+
+        let check_name = |name: &str| {
+            if name.is_empty() {
+                Err(err("empty name"))
+            } else if name.contains(':') {
+                Err(err("names cannot have a namespace prefix"))
+            } else if !is_name(name) {
+                Err(err("invalid name"))
+            } else {
+                Ok(())
+            }
+        };
+
+        let (elements, attribute) = match input.split_once('@') {
+            Some((elements, attribute)) => {
+                check_name(attribute)?;
+                (elements, Some(attribute.to_owned()))
+            }
+            None => (input, None),
+        };
+
+        let steps = elements
+            .split('.')
+            .map(|step| match step {
+                "*" => Ok(Step::AnyChild),
+                "**" => Ok(Step::Descendants),
+                name => check_name(name).map(|_| Step::Name(name.to_owned())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if attribute.is_none() && steps.last() == Some(&Step::Descendants) {
+            return Err(err("path cannot end with '**'"));
+        }
+
+        Ok(XmlPath { steps, attribute })
+    }
+}
+
+impl fmt::Display for XmlPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", step)?;
+        }
+
+        if let Some(attribute) = &self.attribute {
+            write!(f, "@{}", attribute)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl XmlPath {
+    /// Parses a path that refers to an attribute or to an element below the
+    /// root element, which cannot be removed or added.
+    pub(crate) fn child(input: &str) -> Result<Self, InvalidXmlPath> {
+        let path = XmlPath::try_from(input)?;
+
+        if path.attribute.is_none() && path.steps.len() < 2 {
+            return Err(InvalidXmlPath {
+                path: input.to_owned(),
+                reason: "path cannot refer to the root element",
+            });
+        }
+
+        Ok(path)
+    }
+
+    /// Parses a path that refers to an attribute or to a named element below
+    /// the root element, so that it can be created.
+    pub(crate) fn named(input: &str) -> Result<Self, InvalidXmlPath> {
+        let path = XmlPath::child(input)?;
+
+        match (&path.attribute, path.steps.last()) {
+            (Some(_), _) | (None, Some(Step::Name(_))) => Ok(path),
+            _ => Err(InvalidXmlPath {
+                path: input.to_owned(),
+                reason: "path must end with an element name",
+            }),
+        }
+    }
+
+    /// Parses a path that refers to a named element below the root element.
+    pub(crate) fn named_element(input: &str) -> Result<Self, InvalidXmlPath> {
+        let path = XmlPath::named(input)?;
+
+        if path.attribute.is_some() {
+            return Err(InvalidXmlPath {
+                path: input.to_owned(),
+                reason: "path cannot refer to an attribute",
+            });
+        }
+
+        Ok(path)
+    }
+
+    /// Parses the source path and the new name of a rename. Elements and
+    /// attributes are renamed in place and keep their namespace prefix.
+    pub(crate) fn rename_pair(from: &str, to: &str) -> Result<(Self, String), InvalidXmlPath> {
+        let path = XmlPath::try_from(from)?;
+
+        if !is_name(to) {
+            return Err(InvalidXmlPath {
+                path: to.to_owned(),
+                reason: "expected an element or attribute name",
+            });
+        }
+
+        Ok((path, to.to_owned()))
+    }
+
+    /// Splits an element path into the path of the parent elements and the
+    /// last step.
+    fn split_last(&self) -> (&[Step], &Step) {
+        let (last, parent) = self.steps.split_last().expect("XML paths are never empty");
+        (parent, last)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidXml(String);
+
+impl fmt::Display for InvalidXml {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Element(Element),
+    /// Character data, as written (i.e. escaped).
+    Text(String),
+    /// Comments, CDATA sections, processing instructions and declarations,
+    /// as written.
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Attribute {
+    /// The whitespace before the name.
+    lead: String,
+    name: String,
+    /// The `=` and any whitespace around it.
+    eq: String,
+    quote: char,
+    /// The value, as written (i.e. escaped).
+    value: String,
+}
+
+impl Attribute {
+    fn new(name: &str, value: &str) -> Self {
+        Attribute {
+            lead: " ".to_owned(),
+            name: name.to_owned(),
+            eq: "=".to_owned(),
+            quote: '"',
+            value: escape(value).into_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Element {
+    name: String,
+    attributes: Vec<Attribute>,
+    /// The whitespace after the attributes of the start tag.
+    space: String,
+    /// Whether the element was written as an empty-element tag (`<a/>`).
+    empty: bool,
+    children: Vec<Node>,
+    /// The whitespace after the name of the end tag.
+    end_space: String,
+}
+
+impl Element {
+    fn new(name: &str, text: &str) -> Self {
+        Element {
+            name: name.to_owned(),
+            attributes: vec![],
+            space: String::new(),
+            empty: false,
+            children: vec![Node::Text(escape(text).into_owned())],
+            end_space: String::new(),
+        }
+    }
+
+    /// Parses a start tag or empty-element tag.
+    fn parse(raw: &str, empty: bool) -> Result<Self, InvalidXml> {
+        let inner = &raw[1..raw.len() - if empty { 2 } else { 1 }];
+        let name_len = inner
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(inner.len());
+        let name = &inner[..name_len];
+        let err = || InvalidXml(format!("malformed attributes in <{}>", name));
+
+        let mut rest = &inner[name_len..];
+        let mut attributes = vec![];
+
+        loop {
+            let trimmed = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+            if trimmed.is_empty() {
+                break;
+            }
+
+            let lead = &rest[..rest.len() - trimmed.len()];
+            let name_len = trimmed
+                .find(|c: char| c == '=' || c.is_ascii_whitespace())
+                .ok_or_else(err)?;
+            let after_name = &trimmed[name_len..];
+
+            let value_start = after_name.find(['"', '\'']).ok_or_else(err)?;
+            let eq = &after_name[..value_start];
+            if eq.trim() != "=" {
+                return Err(err());
+            }
+
+            let quote = if after_name[value_start..].starts_with('"') {
+                '"'
+            } else {
+                '\''
+            };
+            let value = &after_name[value_start + 1..];
+            let value_len = value.find(quote).ok_or_else(err)?;
+
+            attributes.push(Attribute {
+                lead: lead.to_owned(),
+                name: trimmed[..name_len].to_owned(),
+                eq: eq.to_owned(),
+                quote,
+                value: value[..value_len].to_owned(),
+            });
+
+            rest = &value[value_len + 1..];
+        }
+
+        Ok(Element {
+            name: name.to_owned(),
+            attributes,
+            space: rest.to_owned(),
+            empty,
+            children: vec![],
+            end_space: String::new(),
+        })
+    }
+
+    fn elements_mut(&mut self) -> impl Iterator<Item = &mut Element> {
+        self.children.iter_mut().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    /// Replaces the content of the element with text. Returns whether it
+    /// changed.
+    fn set_text(&mut self, text: &str) -> bool {
+        let text = escape(text);
+
+        if let [Node::Text(current)] = self.children.as_slice() {
+            if *current == text {
+                return false;
+            }
+        }
+
+        self.children = vec![Node::Text(text.into_owned())];
+        true
+    }
+
+    /// Adds a child element after the last one, with the same indentation.
+    fn push_element(&mut self, child: Element) {
+        let last = self
+            .children
+            .iter()
+            .rposition(|node| matches!(node, Node::Element(_)));
+
+        let Some(i) = last else {
+            self.children.push(Node::Element(child));
+            return;
+        };
+
+        let mut at = i + 1;
+
+        if let Some(Node::Text(indent)) = i.checked_sub(1).map(|j| &self.children[j]) {
+            if is_ws(indent) {
+                self.children.insert(at, Node::Text(indent.clone()));
+                at += 1;
+            }
+        }
+
+        self.children.insert(at, Node::Element(child));
+    }
+
+    /// Removes the child elements matched by `step`, along with the
+    /// whitespace before them. Returns how many were removed.
+    fn remove_elements(&mut self, step: &Step) -> usize {
+        let mut removed = 0;
+        let mut kept = Vec::with_capacity(self.children.len());
+
+        for node in self.children.drain(..) {
+            match node {
+                Node::Element(element) if step.matches(&element) => {
+                    removed += 1;
+
+                    if matches!(kept.last(), Some(Node::Text(text)) if is_ws(text)) {
+                        kept.pop();
+                    }
+                }
+                node => kept.push(node),
+            }
+        }
+
+        self.children = kept;
+        removed
+    }
+}
+
+/// Calls `f` with every element selected by `steps`, starting from the
+/// children of `element`.
+fn walk(element: &mut Element, steps: &[Step], f: &mut dyn FnMut(&mut Element)) {
+    let Some((step, rest)) = steps.split_first() else {
+        f(element);
+        return;
+    };
+
+    if let Step::Descendants = step {
+        // visit descendants first so that anything the caller adds to this
+        // element is not visited again
+        element
+            .elements_mut()
+            .for_each(|child| walk(child, steps, f));
+
+        walk(element, rest, f);
+        return;
+    }
+
+    element
+        .elements_mut()
+        .filter(|child| step.matches(child))
+        .for_each(|child| walk(child, rest, f));
+}
+
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}", self.name)?;
+
+        for a in &self.attributes {
+            write!(
+                f,
+                "{}{}{}{}{}{}",
+                a.lead, a.name, a.eq, a.quote, a.value, a.quote
+            )?;
+        }
+
+        if self.empty && self.children.is_empty() {
+            return write!(f, "{}/>", self.space);
+        }
+
+        write!(f, "{}>", self.space)?;
+
+        for child in &self.children {
+            write!(f, "{}", child)?;
+        }
+
+        write!(f, "</{}{}>", self.name, self.end_space)
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Element(element) => write!(f, "{}", element),
+            Node::Text(raw) | Node::Other(raw) => f.write_str(raw),
+        }
+    }
+}
+
+/// An XML document, which can be transformed with `XmlPath`s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Document(Vec<Node>);
+
+impl Document {
+    pub(crate) fn parse(input: &str) -> Result<Self, InvalidXml> {
+        let mut reader = Reader::from_str(input);
+        let mut open: Vec<Element> = vec![];
+        let mut nodes = vec![];
+
+        loop {
+            let start = reader.buffer_position() as usize;
+            let event = reader.read_event().map_err(|e| {
+                InvalidXml(format!("{} at position {}", e, reader.error_position()))
+            })?;
+            let raw = &input[start..reader.buffer_position() as usize];
+
+            let node = match event {
+                Event::Eof => break,
+                Event::Start(_) => {
+                    open.push(Element::parse(raw, false)?);
+                    continue;
+                }
+                Event::Empty(_) => Node::Element(Element::parse(raw, true)?),
+                Event::End(_) => {
+                    let mut element = open
+                        .pop()
+                        .ok_or_else(|| InvalidXml("unexpected end tag".to_owned()))?;
+                    let name = raw[2..raw.len() - 1].trim_end();
+                    element.end_space = raw[2 + name.len()..raw.len() - 1].to_owned();
+                    Node::Element(element)
+                }
+                Event::Text(_) => Node::Text(raw.to_owned()),
+                _ => Node::Other(raw.to_owned()),
+            };
+
+            match open.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => nodes.push(node),
+            }
+        }
+
+        if let Some(element) = open.last() {
+            return Err(InvalidXml(format!("unclosed element <{}>", element.name)));
+        }
+
+        if !nodes.iter().any(|node| matches!(node, Node::Element(_))) {
+            return Err(InvalidXml("no root element".to_owned()));
+        }
+
+        Ok(Document(nodes))
+    }
+
+    /// Calls `f` with every element selected by `steps`, starting from the
+    /// root element.
+    fn for_each(&mut self, steps: &[Step], mut f: impl FnMut(&mut Element)) {
+        let Some((step, rest)) = steps.split_first() else {
+            return;
+        };
+
+        for node in &mut self.0 {
+            let Node::Element(root) = node else {
+                continue;
+            };
+
+            match step {
+                Step::Descendants => walk(root, steps, &mut f),
+                step if step.matches(root) => walk(root, rest, &mut f),
+                _ => {}
+            }
+        }
+    }
+
+    /// Removes the elements or attributes matched by a path. Returns how many
+    /// were removed.
+    pub(crate) fn remove(&mut self, path: &XmlPath) -> usize {
+        let mut removed = 0;
+
+        match &path.attribute {
+            Some(attribute) => self.for_each(&path.steps, |element| {
+                let before = element.attributes.len();
+                element
+                    .attributes
+                    .retain(|a| local_name(&a.name) != attribute);
+                removed += before - element.attributes.len();
+            }),
+            None => {
+                let (parent, last) = path.split_last();
+                self.for_each(parent, |element| removed += element.remove_elements(last));
+            }
+        }
+
+        removed
+    }
+
+    /// Renames the elements or attributes matched by a path. Returns how
+    /// many were renamed.
+    pub(crate) fn rename(&mut self, path: &XmlPath, to: &str) -> usize {
+        let mut count = 0;
+
+        match &path.attribute {
+            Some(attribute) => self.for_each(&path.steps, |element| {
+                let mut names = vec![];
+                let mut is_renamed = vec![];
+
+                for a in &mut element.attributes {
+                    let matches = local_name(&a.name) == attribute;
+
+                    if matches {
+                        a.name = renamed(&a.name, to);
+                        names.push(a.name.clone());
+                        count += 1;
+                    }
+
+                    is_renamed.push(matches);
+                }
+
+                // a renamed attribute replaces any existing one with its name
+                let mut i = 0;
+                element.attributes.retain(|a| {
+                    let keep = is_renamed[i] || !names.contains(&a.name);
+                    i += 1;
+                    keep
+                });
+            }),
+            None => self.for_each(&path.steps, |element| {
+                element.name = renamed(&element.name, to);
+                count += 1;
+            }),
+        }
+
+        count
+    }
+
+    /// Replaces the text of the elements, or the values of the attributes,
+    /// matched by a path. Returns how many were changed.
+    pub(crate) fn replace(&mut self, path: &XmlPath, value: &str) -> usize {
+        let mut changed = 0;
+
+        match &path.attribute {
+            Some(attribute) => {
+                let escaped = escape(value);
+
+                self.for_each(&path.steps, |element| {
+                    for a in &mut element.attributes {
+                        if local_name(&a.name) == attribute && a.value != escaped {
+                            a.value = escaped.clone().into_owned();
+                            changed += 1;
+                        }
+                    }
+                });
+            }
+            None => self.for_each(&path.steps, |element| {
+                if element.set_text(value) {
+                    changed += 1;
+                }
+            }),
+        }
+
+        changed
+    }
+
+    /// Adds the element or attribute named by a path, with the given text or
+    /// value, to each parent element that does not have it yet. Returns how
+    /// many were added.
+    pub(crate) fn add(&mut self, path: &XmlPath, value: &str) -> usize {
+        let mut added = 0;
+
+        match &path.attribute {
+            Some(attribute) => self.for_each(&path.steps, |element| {
+                if !element
+                    .attributes
+                    .iter()
+                    .any(|a| local_name(&a.name) == attribute)
+                {
+                    element.attributes.push(Attribute::new(attribute, value));
+                    added += 1;
+                }
+            }),
+            None => {
+                let (parent, Step::Name(name)) = path.split_last() else {
+                    return 0;
+                };
+
+                self.for_each(parent, |element| {
+                    if !element
+                        .children
+                        .iter()
+                        .any(|node| matches!(node, Node::Element(e) if local_name(&e.name) == name))
+                    {
+                        element.push_element(Element::new(name, value));
+                        added += 1;
+                    }
+                });
+            }
+        }
+
+        added
+    }
+
+    /// Adds an element named by a path, with the given text, to each parent
+    /// element. Returns how many were added.
+    pub(crate) fn append(&mut self, path: &XmlPath, value: &str) -> usize {
+        let (parent, Step::Name(name)) = path.split_last() else {
+            return 0;
+        };
+
+        let mut added = 0;
+
+        self.for_each(parent, |element| {
+            element.push_element(Element::new(name, value));
+            added += 1;
+        });
+
+        added
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.0 {
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOAP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- response -->
+<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/">
+  <soap:Body>
+    <m:GetUserResponse xmlns:m="urn:users">
+      <m:User id='42' xsi:type = "m:User">
+        <m:name>Ana &amp; Bo</m:name>
+        <m:password>hunter2</m:password>
+        <m:notes><![CDATA[<b>hi</b>]]></m:notes>
+        <m:empty />
+      </m:User>
+    </m:GetUserResponse>
+  </soap:Body>
+</soap:Envelope >
+"#;
+
+    fn path(s: &str) -> XmlPath {
+        XmlPath::try_from(s).unwrap()
+    }
+
+    fn transform(input: &str, f: impl FnOnce(&mut Document) -> usize) -> (usize, String) {
+        let mut doc = Document::parse(input).unwrap();
+        let count = f(&mut doc);
+        (count, doc.to_string())
+    }
+
+    #[test]
+    fn test_parse_paths() {
+        for input in [
+            "a", "a.b", "a.*.c", "**.c", "a.**.c", "a@id", "**@id", "a.b-c_d",
+        ] {
+            assert_eq!(input, path(input).to_string());
+        }
+
+        for input in [
+            "",
+            ".",
+            "a.",
+            ".a",
+            "a..b",
+            "@id",
+            "a@",
+            "a@b@c",
+            "a.**",
+            "soap:Body",
+            "a@xsi:type",
+            "a b",
+            "1a",
+            "a.<b>",
+        ] {
+            assert!(XmlPath::try_from(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_path_kinds() {
+        assert!(XmlPath::child("a.b").is_ok());
+        assert!(XmlPath::child("a@id").is_ok());
+        assert!(XmlPath::child("a").is_err());
+        assert!(XmlPath::child("*").is_err());
+
+        assert!(XmlPath::named("a.b").is_ok());
+        assert!(XmlPath::named("a.*@id").is_ok());
+        assert!(XmlPath::named("a.*").is_err());
+
+        assert!(XmlPath::named_element("a.b").is_ok());
+        assert!(XmlPath::named_element("a.b@id").is_err());
+
+        assert!(XmlPath::rename_pair("a.b", "c").is_ok());
+        assert!(XmlPath::rename_pair("a.b", "a.c").is_err());
+        assert!(XmlPath::rename_pair("a.b", "m:c").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let doc = Document::parse(SOAP).unwrap();
+        assert_eq!(SOAP, doc.to_string());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for input in ["", "text", "<a>", "<a></b>", "<a x=1/>", "</a>"] {
+            assert!(Document::parse(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_remove() {
+        let (count, output) = transform(SOAP, |doc| {
+            doc.remove(&path("Envelope.Body.*.User.password"))
+                + doc.remove(&path("**.User@type"))
+                + doc.remove(&path("**.missing"))
+        });
+
+        assert_eq!(2, count);
+        assert_eq!(
+            SOAP.replace("\n        <m:password>hunter2</m:password>", "")
+                .replace(r#" xsi:type = "m:User""#, ""),
+            output
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let (count, output) = transform(SOAP, |doc| {
+            doc.rename(&path("**.password"), "secret") + doc.rename(&path("**.User@id"), "userId")
+        });
+
+        assert_eq!(2, count);
+        assert_eq!(
+            SOAP.replace("m:password>", "m:secret>")
+                .replace("id='42'", "userId='42'"),
+            output
+        );
+
+        let (count, output) = transform(r#"<a x="1" y="2"/>"#, |doc| doc.rename(&path("a@x"), "y"));
+        assert_eq!(1, count);
+        assert_eq!(r#"<a y="1"/>"#, output);
+
+        let (_, output) = transform(r#"<a y="2" x="1"/>"#, |doc| doc.rename(&path("a@x"), "y"));
+        assert_eq!(r#"<a y="1"/>"#, output);
+    }
+
+    #[test]
+    fn test_replace() {
+        let (count, output) = transform(SOAP, |doc| {
+            doc.replace(&path("**.password"), "<redacted>")
+                + doc.replace(&path("**.User@id"), "0")
+                + doc.replace(&path("**.User@missing"), "0")
+                + doc.replace(&path("**.empty"), "x")
+                + doc.replace(&path("**.name"), "Ana & Bo")
+        });
+
+        assert_eq!(3, count);
+        assert_eq!(
+            SOAP.replace("hunter2", "&lt;redacted&gt;")
+                .replace("id='42'", "id='0'")
+                .replace("<m:empty />", "<m:empty >x</m:empty>"),
+            output
+        );
+    }
+
+    #[test]
+    fn test_add() {
+        let (count, output) = transform(SOAP, |doc| {
+            doc.add(&path("**.User.name"), "x")
+                + doc.add(&path("**.User.role"), "admin")
+                + doc.add(&path("**.User@id"), "0")
+                + doc.add(&path("**.User@active"), "true")
+                + doc.add(&path("Envelope.Header"), "")
+        });
+
+        assert_eq!(3, count);
+        assert_eq!(
+            SOAP.replace("<m:empty />", "<m:empty />\n        <role>admin</role>")
+                .replace(r#""m:User">"#, r#""m:User" active="true">"#)
+                .replace("  </soap:Body>\n", "  </soap:Body>\n  <Header></Header>\n"),
+            output
+        );
+    }
+
+    #[test]
+    fn test_append() {
+        let (count, output) = transform("<a><b>1</b></a>", |doc| {
+            doc.append(&path("a.b"), "2") + doc.append(&path("a.c.d"), "3")
+        });
+
+        assert_eq!(1, count);
+        assert_eq!("<a><b>1</b><b>2</b></a>", output);
+
+        let (count, output) = transform("<a/>", |doc| doc.append(&path("a.b"), "1"));
+        assert_eq!(1, count);
+        assert_eq!("<a><b>1</b></a>", output);
+    }
+}